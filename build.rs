@@ -5,51 +5,37 @@ use parcel_css::stylesheet::{ParserOptions, PrinterOptions, StyleSheet};
 use sha2::{Digest, Sha256};
 
 fn main() {
-    let out_dir = env::var_os("OUT_DIR").unwrap();
-
     // normalize.css
     println!("cargo:rerun-if-changed=src/theme/normalize.css");
-    let normalize = include_str!("src/theme/normalize.css");
-    let file_name = format!("normalize-{}.css", hash(normalize));
-    let normalize = StyleSheet::parse(
-        "normalize.css".to_string(),
-        normalize,
-        ParserOptions {
-            nesting: true,
-            css_modules: false,
-        },
-    )
-    .unwrap();
-    let normalize = normalize
-        .to_css(PrinterOptions {
-            minify: true,
-            source_map: false,
-            targets: None,
-            analyze_dependencies: true,
-            pseudo_classes: None,
-        })
-        .unwrap();
-    assert!(
-        normalize.dependencies.unwrap().is_empty(),
-        "CSS dependencies are not supported yet"
-    );
-    fs::write(Path::new(&out_dir).join(&file_name), normalize.code).unwrap();
+    let file_name = minify("normalize", include_str!("src/theme/normalize.css"));
     println!("cargo:rustc-env=NORMALIZE_CSS={}", file_name);
 
     // style.css
     println!("cargo:rerun-if-changed=src/theme/style.css");
-    let style = include_str!("src/theme/style.css");
-    let file_name = format!("style-{}.css", hash(style));
-    let style = StyleSheet::parse(
-        "style.css".to_string(),
-        style,
+    let file_name = minify("style", include_str!("src/theme/style.css"));
+    println!("cargo:rustc-env=STYLE_CSS={}", file_name);
+
+    // highlight.css
+    println!("cargo:rerun-if-changed=src/theme/highlight.css");
+    let file_name = minify("highlight", include_str!("src/theme/highlight.css"));
+    println!("cargo:rustc-env=HIGHLIGHT_CSS={}", file_name);
+}
+
+/// Minifies the stylesheet and writes it to `OUT_DIR` as `<name>-<hash>.css`. Returns the file's
+/// name.
+fn minify(name: &str, content: &str) -> String {
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    let file_name = format!("{}-{}.css", name, hash(content));
+    let stylesheet = StyleSheet::parse(
+        format!("{}.css", name),
+        content,
         ParserOptions {
             nesting: true,
             css_modules: false,
         },
     )
     .unwrap();
-    let style = style
+    let stylesheet = stylesheet
         .to_css(PrinterOptions {
             minify: true,
             source_map: false,
@@ -59,11 +45,11 @@ fn main() {
         })
         .unwrap();
     assert!(
-        style.dependencies.unwrap().is_empty(),
+        stylesheet.dependencies.unwrap().is_empty(),
         "CSS dependencies are not supported yet"
     );
-    fs::write(Path::new(&out_dir).join(&file_name), style.code).unwrap();
-    println!("cargo:rustc-env=STYLE_CSS={}", file_name);
+    fs::write(Path::new(&out_dir).join(&file_name), stylesheet.code).unwrap();
+    file_name
 }
 
 fn hash(content: &str) -> String {
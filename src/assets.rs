@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::{content_hash, hash_and_write, Error, Post};
+use crate::config::CONFIG;
+use crate::discover;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use url::Url;
+
+/// The file the manifest of all assets is written to in the out dir.
+const MANIFEST_FILE: &str = "assets.json";
+
+/// The file names of the assets written so far by their content hash, so that identical files
+/// (e.g. the same image next to different posts) are only written once.
+static WRITTEN: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(Default::default);
+
+/// A local file referenced by a post that got written to the out dir.
+pub struct Asset {
+    /// The referenced file.
+    pub path: PathBuf,
+    pub content: Vec<u8>,
+    /// The file name of the asset in the out dir.
+    pub file_name: String,
+    /// The URL the reference is rewritten to, i.e. the file name followed by the original query
+    /// and fragment.
+    pub url: String,
+}
+
+/// Resolves a URL relative to `base` (the directory of the post) and writes the file it references
+/// to the out dir. Returns `None` for absolute URLs and paths, and for URLs that don't reference an
+/// existing file.
+pub fn resolve(base: &Path, url: &str) -> Result<Option<Asset>, Error> {
+    let (path, suffix) = match local_path(base, url) {
+        Some((path, suffix)) if path.is_file() => (path, suffix),
+        _ => return Ok(None),
+    };
+
+    let content = fs::read(&path)?;
+    let file_name = write(&path, &content)?;
+    Ok(Some(Asset {
+        url: format!("{}{}", file_name, suffix),
+        path,
+        content,
+        file_name,
+    }))
+}
+
+/// Resolves a URL relative to `base` that references a markdown file to the URL of the file's page
+/// (keeping the query and fragment), and returns it together with the canonical path of the file.
+/// Returns `None` for URLs that don't reference a markdown file, and the referenced file as error
+/// if it is not one of the site's `pages` (which are keyed by their canonical path).
+pub fn resolve_page(
+    base: &Path,
+    url: &str,
+    pages: &HashMap<PathBuf, String>,
+) -> Result<Option<(String, PathBuf)>, PathBuf> {
+    let (path, suffix) = match local_path(base, url) {
+        Some((path, suffix)) if discover::is_markdown(&path) => (path, suffix),
+        _ => return Ok(None),
+    };
+    let canonical = fs::canonicalize(&path).map_err(|_| path.clone())?;
+    match pages.get(&canonical) {
+        Some(file_name) => Ok(Some((format!("/{}{}", file_name, suffix), canonical))),
+        None => Err(path),
+    }
+}
+
+/// Splits a relative URL into the path it references (relative to `base`) and its query and
+/// fragment. Returns `None` for absolute URLs and paths, and URLs without a path.
+fn local_path<'a>(base: &Path, url: &'a str) -> Option<(PathBuf, &'a str)> {
+    // Url::parse only succeeds for absolute URLs
+    if url.is_empty() || Url::parse(url).is_ok() {
+        return None;
+    }
+
+    let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    let path = Path::new(path);
+    if path.as_os_str().is_empty() || path.is_absolute() {
+        return None;
+    }
+    Some((base.join(path), suffix))
+}
+
+/// Resolves each URL of a `srcset` list (e.g. `small.png 1x, large.png 2x`) via [`resolve`].
+/// Returns the rewritten list and the assets it references.
+pub fn resolve_srcset(base: &Path, srcset: &str) -> Result<(String, Vec<Asset>), Error> {
+    let mut candidates = Vec::new();
+    let mut assets = Vec::new();
+    for candidate in srcset.split(',').map(str::trim) {
+        let (url, descriptor) = candidate
+            .split_once(char::is_whitespace)
+            .unwrap_or((candidate, ""));
+        match resolve(base, url)? {
+            Some(asset) => {
+                candidates.push(format!("{} {}", asset.url, descriptor.trim()));
+                assets.push(asset);
+            }
+            None => candidates.push(candidate.to_string()),
+        }
+    }
+    let srcset = candidates
+        .iter()
+        .map(|candidate| candidate.trim_end())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok((srcset, assets))
+}
+
+/// Writes the file to the out dir, unless a file with the same content was already written.
+fn write(path: &Path, content: &[u8]) -> Result<String, Error> {
+    let hash = content_hash(content);
+    let mut written = WRITTEN.lock().unwrap();
+    if let Some(file_name) = written.get(&hash) {
+        // the out dir might have been cleaned in the meantime
+        if Path::new(CONFIG.out_dir()).join(file_name).is_file() {
+            return Ok(file_name.clone());
+        }
+    }
+
+    let name = path.file_stem().and_then(|n| n.to_str()).unwrap_or("asset");
+    let ext = path.extension().and_then(|n| n.to_str());
+    let file_name = hash_and_write(name, ext, content)?;
+    written.insert(hash, file_name.clone());
+    Ok(file_name)
+}
+
+#[derive(Default, Serialize)]
+struct ManifestEntry<'a> {
+    /// The files the asset was created from.
+    sources: BTreeSet<&'a Path>,
+    /// The pages referencing the asset.
+    pages: BTreeSet<&'a str>,
+}
+
+/// Writes `assets.json` to the out dir, which lists every asset written for the given (published)
+/// posts.
+pub fn write_manifest(posts: &[&Post]) -> Result<(), Error> {
+    let mut manifest = BTreeMap::<&str, ManifestEntry>::new();
+    for &post in posts {
+        for (file_name, source) in &post.assets {
+            let entry = manifest.entry(file_name).or_default();
+            entry.sources.insert(source);
+            entry.pages.insert(&post.file_name);
+        }
+    }
+
+    let json = serde_json::to_string_pretty(&manifest).map_err(Error::Manifest)?;
+    fs::write(Path::new(CONFIG.out_dir()).join(MANIFEST_FILE), json)?;
+    Ok(())
+}
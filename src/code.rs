@@ -0,0 +1,651 @@
+use std::path::Path;
+use std::{fs, process};
+
+use super::{escape_attr, Error};
+use crate::config::CONFIG;
+use once_cell::sync::Lazy;
+use pulldown_cmark::escape::escape_html;
+use syntect::dumps;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::{Scope, SyntaxDefinition, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// The file in the syntaxes directory that `mdr dump-syntaxes` compiles all syntax definitions
+/// into. If it exists, it is loaded instead of building the syntax set from scratch.
+const SYNTAX_DUMP_FILE: &str = "syntaxes.packdump";
+
+static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(|| match load_syntax_set() {
+    Ok(syntax_set) => syntax_set,
+    Err(err) => {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+});
+
+fn load_syntax_set() -> Result<SyntaxSet, Error> {
+    if let Some(dump) = CONFIG
+        .syntaxes()
+        .map(|dir| dir.join(SYNTAX_DUMP_FILE))
+        .filter(|dump| dump.is_file())
+    {
+        return dumps::from_dump_file(&dump).map_err(|err| Error::SyntaxDump(dump, Box::new(err)));
+    }
+    build_syntax_set()
+}
+
+/// Builds the set of syntect's default syntaxes, the bundled TypeScript syntaxes and the ones of
+/// the site's syntaxes directory.
+fn build_syntax_set() -> Result<SyntaxSet, Error> {
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    builder.add(
+        SyntaxDefinition::load_from_str(
+            include_str!("../syntax/TypeScript.sublime-syntax"),
+            true,
+            None,
+        )
+        .unwrap(),
+    );
+    builder.add(
+        SyntaxDefinition::load_from_str(
+            include_str!("../syntax/TypeScriptReact.sublime-syntax"),
+            true,
+            None,
+        )
+        .unwrap(),
+    );
+    if let Some(dir) = CONFIG.syntaxes() {
+        builder
+            .add_from_folder(dir, true)
+            .map_err(|err| Error::Syntax(dir.to_path_buf(), err))?;
+    }
+    Ok(builder.build())
+}
+
+/// Compiles all syntaxes (including the ones of the site's syntaxes directory) into a dump, which
+/// is faster to load than building the syntax set on every run.
+pub fn dump_syntaxes() -> Result<(), Error> {
+    let dir = CONFIG.syntaxes().ok_or(Error::MissingSyntaxes)?;
+    let syntax_set = build_syntax_set()?;
+    let dump = dir.join(SYNTAX_DUMP_FILE);
+    dumps::dump_to_file(&syntax_set, &dump)
+        .map_err(|err| Error::SyntaxDump(dump.clone(), Box::new(err)))?;
+    println!("wrote {}", dump.display());
+    Ok(())
+}
+
+/// Generates the highlighting CSS for the configured theme (and dark theme), or returns `None` if
+/// no theme is configured.
+pub fn theme_css() -> Result<Option<String>, Error> {
+    let highlight = CONFIG.highlight();
+    let theme = match &highlight.theme {
+        Some(theme) => theme,
+        None => return Ok(None),
+    };
+
+    let mut themes = ThemeSet::load_defaults();
+    if let Some(dir) = CONFIG.themes() {
+        themes
+            .add_from_folder(dir)
+            .map_err(|err| Error::Theme(dir.to_path_buf(), err))?;
+    }
+    let get = |name: &str| {
+        themes.themes.get(name).ok_or_else(|| {
+            let available = themes.themes.keys().cloned().collect::<Vec<_>>();
+            Error::UnknownTheme(name.to_string(), available.join(", "))
+        })
+    };
+
+    let mut css = theme_to_css(get(theme)?);
+    if let Some(dark_theme) = &highlight.dark_theme {
+        css += &format!(
+            "@media (prefers-color-scheme: dark) {{\n{}}}\n",
+            theme_to_css(get(dark_theme)?)
+        );
+    }
+    Ok(Some(css))
+}
+
+/// Creates the CSS for the highlighting classes of the theme, and sets the colors of code blocks
+/// to the theme's ones.
+fn theme_to_css(theme: &Theme) -> String {
+    let settings = &theme.settings;
+    let mut css = ":root {\n".to_string();
+    for (var, color) in [
+        ("--code-theme-fg", settings.foreground),
+        ("--code-theme-bg", settings.background),
+        ("--code-theme-highlight", settings.line_highlight),
+    ] {
+        if let Some(color) = color {
+            css += &format!(
+                "  {}: #{:02x}{:02x}{:02x};\n",
+                var, color.r, color.g, color.b
+            );
+        }
+    }
+    css += "}\n";
+    css += &css_for_theme_with_class_style(theme, ClassStyle::Spaced);
+    css
+}
+
+/// The options of a fenced code block parsed from its info string, e.g.
+/// `rust {3,5-7} linenos title="src/main.rs"`.
+#[derive(Debug, Default)]
+pub struct CodeInfo {
+    pub lang: Option<String>,
+    /// Line ranges (1-based, inclusive) to emphasise.
+    pub highlight: Vec<(usize, usize)>,
+    pub line_numbers: bool,
+    /// Shown as a caption above the code block, usually the name of the file.
+    pub title: Option<String>,
+    /// Set for `diff-<lang>` blocks, whose lines start with `+`, `-` or a space.
+    pub diff: bool,
+    /// A file (relative to the post) whose code is shown instead of the block's content.
+    pub file: Option<String>,
+    /// The line ranges of the file to include.
+    pub lines: Vec<(usize, usize)>,
+    /// The region of the file to include, marked by `ANCHOR: <name>` and `ANCHOR_END: <name>`
+    /// lines.
+    pub region: Option<String>,
+}
+
+/// The change of a line in a `diff-<lang>` block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    Added,
+    Removed,
+    Unchanged,
+}
+
+impl CodeInfo {
+    pub fn parse(info: &str) -> Self {
+        let mut code_info = CodeInfo::default();
+        for (i, token) in tokenize(info).into_iter().enumerate() {
+            if let Some(ranges) = token.strip_prefix('{') {
+                code_info
+                    .highlight
+                    .extend(parse_ranges(ranges.trim_end_matches('}')));
+                continue;
+            }
+
+            match token.split_once('=') {
+                Some(("title", value)) => code_info.title = Some(unquote(value).to_string()),
+                Some(("file", value)) => code_info.file = Some(unquote(value).to_string()),
+                Some(("lines", value)) => code_info.lines = parse_ranges(unquote(value)),
+                Some(("region", value)) => code_info.region = Some(unquote(value).to_string()),
+                Some(_) => {}
+                None if token == "linenos" => code_info.line_numbers = true,
+                None if i == 0 => match token.strip_prefix("diff-") {
+                    Some(lang) if !lang.is_empty() => {
+                        code_info.diff = true;
+                        code_info.lang = Some(lang.to_string());
+                    }
+                    _ => code_info.lang = Some(token),
+                },
+                None => {}
+            }
+        }
+        code_info
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlight
+            .iter()
+            .any(|&(start, end)| start <= line && line <= end)
+    }
+}
+
+/// Splits the info string at whitespace, while keeping quoted values and `{...}` line ranges
+/// together. A range directly following the language (`rust{3}`) is split off into its own token.
+fn tokenize(info: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut braced = false;
+    for c in info.chars() {
+        match c {
+            '"' if !braced => quoted = !quoted,
+            '{' if !quoted && !braced => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                braced = true;
+            }
+            '}' if braced => braced = false,
+            c if c.is_whitespace() && !quoted && !braced => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        if !(braced && c.is_whitespace()) {
+            token.push(c);
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// Parses comma separated line numbers and ranges like `3,5-7`; invalid entries are ignored.
+pub fn parse_ranges(s: &str) -> Vec<(usize, usize)> {
+    s.split(',')
+        .filter_map(|range| {
+            let range = range.trim();
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let start = start.trim().parse().ok()?;
+            let end = end.trim().parse().ok()?;
+            (start <= end).then_some((start, end))
+        })
+        .collect()
+}
+
+fn find_syntax(lang: &str) -> Option<&'static SyntaxReference> {
+    Scope::new(&format!("source.{}", lang))
+        .ok()
+        .and_then(|scope| SYNTAX_SET.find_syntax_by_scope(scope))
+        .or_else(|| SYNTAX_SET.find_syntax_by_name(lang))
+        .or_else(|| SYNTAX_SET.find_syntax_by_extension(lang))
+}
+
+/// Renders a fenced code block, highlighting its code if the language is known. `line_numbers` are
+/// the numbers of the code's lines in their file if it was included, otherwise lines are numbered
+/// from 1.
+pub fn render(info: &CodeInfo, code: &str, line_numbers: &[usize]) -> String {
+    let (code, changes) = if info.diff {
+        strip_diff_markers(code)
+    } else {
+        (code.to_string(), Vec::new())
+    };
+    let highlighted = highlight(info.lang.as_deref(), &code);
+
+    let mut html = String::new();
+    if let Some(title) = &info.title {
+        html += &format!(
+            r#"<figure class="code-block"><figcaption>{}</figcaption>"#,
+            escape_attr(title)
+        );
+    }
+
+    let classes = [(info.line_numbers, "line-numbers"), (info.diff, "diff")]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, class)| *class)
+        .collect::<Vec<_>>();
+    if classes.is_empty() {
+        html += "<pre>";
+    } else {
+        html += &format!(r#"<pre class="{}">"#, classes.join(" "));
+    }
+    match &info.lang {
+        Some(lang) => html += &format!(r#"<code class="language-{}">"#, escape_attr(lang)),
+        None => html += "<code>",
+    }
+
+    if info.line_numbers || info.diff || !info.highlight.is_empty() {
+        for (i, line) in split_lines(&highlighted).into_iter().enumerate() {
+            let number = line_numbers.get(i).copied().unwrap_or(i + 1);
+            let mut class = "line".to_string();
+            if info.is_highlighted(number) {
+                class += " highlight";
+            }
+            match changes.get(i) {
+                Some(Change::Added) => class += " added",
+                Some(Change::Removed) => class += " removed",
+                _ => {}
+            }
+            html += &format!(
+                r#"<span class="{}" data-line="{}">{}</span>"#,
+                class, number, line
+            );
+            html.push('\n');
+        }
+    } else {
+        html += &highlighted;
+    }
+
+    html += "</code></pre>";
+    if info.title.is_some() {
+        html += "</figure>";
+    }
+    html.push('\n');
+    html
+}
+
+/// Reads the code of a block with a `file` (already resolved relative to the post), reduced to the
+/// block's `lines` or `region` if set. Region markers are never included. Returns the code and the
+/// (1-based) line number each of its lines has in the file.
+pub fn include(path: &Path, info: &CodeInfo) -> Result<(String, Vec<usize>), Error> {
+    let content =
+        fs::read_to_string(path).map_err(|err| Error::Include(path.to_path_buf(), err))?;
+    let lines = content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .collect::<Vec<_>>();
+    let is_marker = |line: &str| {
+        region_marker(line, "ANCHOR:").is_some() || region_marker(line, "ANCHOR_END:").is_some()
+    };
+
+    let selected = if let Some(region) = &info.region {
+        let missing = || Error::MissingRegion(path.to_path_buf(), region.clone());
+        let start = lines
+            .iter()
+            .position(|(_, line)| region_marker(line, "ANCHOR:") == Some(region))
+            .ok_or_else(missing)?;
+        let end = lines[start + 1..]
+            .iter()
+            .position(|(_, line)| region_marker(line, "ANCHOR_END:") == Some(region))
+            .ok_or_else(missing)?;
+        lines[start + 1..start + 1 + end].to_vec()
+    } else if !info.lines.is_empty() {
+        let mut selected = Vec::new();
+        for &(start, end) in &info.lines {
+            if start == 0 || end > lines.len() {
+                return Err(Error::InvalidLines(path.to_path_buf(), start, end));
+            }
+            selected.extend_from_slice(&lines[start - 1..end]);
+        }
+        selected
+    } else {
+        lines
+    };
+
+    let (numbers, selected): (Vec<_>, Vec<_>) = selected
+        .into_iter()
+        .filter(|(_, line)| !is_marker(line))
+        .unzip();
+    let mut code = dedent(&selected);
+    if !code.ends_with('\n') {
+        code.push('\n');
+    }
+    Ok((code, numbers))
+}
+
+/// Returns the name of the region if the line contains the given marker, e.g. `// ANCHOR: main`.
+fn region_marker<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let (_, rest) = line.split_once(marker)?;
+    rest.split_whitespace().next()
+}
+
+/// Removes the indentation all (non-empty) lines have in common.
+fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or("\n"))
+        .collect()
+}
+
+/// Strips the leading `+`, `-` or space of each line of a diff, and returns the remaining code
+/// together with the change of each line. Lines without a marker are kept as they are.
+fn strip_diff_markers(code: &str) -> (String, Vec<Change>) {
+    let mut stripped = String::with_capacity(code.len());
+    let mut changes = Vec::new();
+    for line in LinesWithEndings::from(code) {
+        let (change, rest) = match line.chars().next() {
+            Some('+') => (Change::Added, &line[1..]),
+            Some('-') => (Change::Removed, &line[1..]),
+            Some(' ') => (Change::Unchanged, &line[1..]),
+            _ => (Change::Unchanged, line),
+        };
+        stripped += rest;
+        changes.push(change);
+    }
+    (stripped, changes)
+}
+
+/// Renders inline code with a language hint (`` `let x = 1;`{:rust} ``).
+pub fn render_inline(lang: &str, code: &str) -> String {
+    // syntaxes expect lines to end with a line break
+    let mut highlighted = highlight(Some(lang), &format!("{}\n", code));
+    if let Some(i) = highlighted.rfind('\n') {
+        highlighted.remove(i);
+    }
+    format!(
+        r#"<code class="language-{}">{}</code>"#,
+        escape_attr(lang),
+        highlighted
+    )
+}
+
+/// Highlights the code if the language is known, otherwise just escapes it.
+fn highlight(lang: Option<&str>, code: &str) -> String {
+    let mut html = String::new();
+    match lang.and_then(find_syntax) {
+        Some(syntax) => {
+            let mut highlighter =
+                ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, ClassStyle::Spaced);
+            for line in LinesWithEndings::from(code) {
+                highlighter.parse_html_for_line_which_includes_newline(line);
+            }
+            html = highlighter.finalize();
+        }
+        None => escape_html(&mut html, code).unwrap(),
+    }
+    html
+}
+
+/// Splits highlighted HTML into lines (without their line breaks). Spans that are still open at
+/// the end of a line are closed and re-opened on the next one, so that each line is well-formed
+/// on its own.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut line = String::new();
+    let mut has_text = false;
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with("<span") {
+            let end = rest.find('>').map(|i| i + 1).unwrap_or(rest.len());
+            open.push(&rest[..end]);
+            line += &rest[..end];
+            rest = &rest[end..];
+        } else if let Some(after) = rest.strip_prefix("</span>") {
+            open.pop();
+            line += "</span>";
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('\n') {
+            line += &"</span>".repeat(open.len());
+            lines.push(std::mem::take(&mut line));
+            line += &open.concat();
+            has_text = false;
+            rest = after;
+        } else {
+            let end = rest.find(['<', '\n']).unwrap_or(rest.len()).max(1);
+            line += &rest[..end];
+            has_text = true;
+            rest = &rest[end..];
+        }
+    }
+    // a trailing line break does not start another line
+    if has_text {
+        line += &"</span>".repeat(open.len());
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_ranges("3,5-7"), [(3, 3), (5, 7)]);
+        assert_eq!(parse_ranges(" 1 - 2 , 4 "), [(1, 2), (4, 4)]);
+    }
+
+    #[test]
+    fn invalid_ranges_are_ignored() {
+        assert_eq!(parse_ranges("5-3"), []);
+        assert_eq!(parse_ranges("x"), []);
+        assert_eq!(parse_ranges("1-x,2,,3-"), [(2, 2)]);
+    }
+
+    #[test]
+    fn info_string() {
+        let info = CodeInfo::parse(r#"rust {3,5-7} linenos title="src/main.rs""#);
+        assert_eq!(info.lang.as_deref(), Some("rust"));
+        assert_eq!(info.highlight, [(3, 3), (5, 7)]);
+        assert!(info.line_numbers);
+        assert_eq!(info.title.as_deref(), Some("src/main.rs"));
+        assert!(!info.diff);
+    }
+
+    #[test]
+    fn info_string_with_range_after_language() {
+        let info = CodeInfo::parse("rust{ 3, 5 }");
+        assert_eq!(info.lang.as_deref(), Some("rust"));
+        assert_eq!(info.highlight, [(3, 3), (5, 5)]);
+    }
+
+    #[test]
+    fn info_string_with_invalid_ranges() {
+        let info = CodeInfo::parse("rust {5-3} {x}");
+        assert_eq!(info.lang.as_deref(), Some("rust"));
+        assert!(info.highlight.is_empty());
+    }
+
+    #[test]
+    fn quoted_title_with_spaces() {
+        assert_eq!(
+            tokenize(r#"rust title="my file.rs" linenos"#),
+            ["rust", r#"title="my file.rs""#, "linenos"]
+        );
+        let info = CodeInfo::parse(r#"rust title="my file.rs" linenos"#);
+        assert_eq!(info.title.as_deref(), Some("my file.rs"));
+        assert!(info.line_numbers);
+    }
+
+    #[test]
+    fn included_file() {
+        let info = CodeInfo::parse(r#"rust file="demo.rs" lines="1-2,5-6" region=main"#);
+        assert_eq!(info.file.as_deref(), Some("demo.rs"));
+        assert_eq!(info.lines, [(1, 2), (5, 6)]);
+        assert_eq!(info.region.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn diff_language() {
+        let info = CodeInfo::parse("diff-rust");
+        assert!(info.diff);
+        assert_eq!(info.lang.as_deref(), Some("rust"));
+
+        let info = CodeInfo::parse("diff-");
+        assert!(!info.diff);
+        assert_eq!(info.lang.as_deref(), Some("diff-"));
+    }
+
+    #[test]
+    fn diff_markers() {
+        let (code, changes) = strip_diff_markers("+a\n-b\n c\nd\n");
+        assert_eq!(code, "a\nb\nc\nd\n");
+        assert_eq!(
+            changes,
+            [
+                Change::Added,
+                Change::Removed,
+                Change::Unchanged,
+                Change::Unchanged
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_are_reopened_on_each_line() {
+        assert_eq!(
+            split_lines("<span class=\"a\">x\n<span class=\"b\">y\nz</span></span>\n"),
+            [
+                "<span class=\"a\">x</span>",
+                "<span class=\"a\"><span class=\"b\">y</span></span>",
+                "<span class=\"a\"><span class=\"b\">z</span></span>",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_lines_are_kept() {
+        assert_eq!(split_lines("a\n\nb"), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn line_numbers_and_highlights() {
+        let info = CodeInfo::parse("{5} linenos");
+        let html = render(&info, "a\nb\nc\n", &[1, 2, 5]);
+        assert_eq!(
+            html,
+            "<pre class=\"line-numbers\"><code>\
+             <span class=\"line\" data-line=\"1\">a</span>\n\
+             <span class=\"line\" data-line=\"2\">b</span>\n\
+             <span class=\"line highlight\" data-line=\"5\">c</span>\n\
+             </code></pre>\n"
+        );
+    }
+
+    fn demo_file() -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "fn a() {{}}\n\n    // ANCHOR: main\n    fn main() {{}}\n    // ANCHOR_END: main\nfn b() {{}}\n"
+        )
+        .unwrap();
+        file
+    }
+
+    #[test]
+    fn include_lines() {
+        let file = demo_file();
+        let info = CodeInfo::parse(r#"file="demo.rs" lines="1,4-6""#);
+        let (code, numbers) = include(file.path(), &info).unwrap();
+        assert_eq!(code, "fn a() {}\n    fn main() {}\nfn b() {}\n");
+        assert_eq!(numbers, [1, 4, 6]);
+    }
+
+    #[test]
+    fn include_region() {
+        let file = demo_file();
+        let info = CodeInfo::parse(r#"file="demo.rs" region="main""#);
+        let (code, numbers) = include(file.path(), &info).unwrap();
+        assert_eq!(code, "fn main() {}\n");
+        assert_eq!(numbers, [4]);
+    }
+
+    #[test]
+    fn include_without_selection_skips_markers() {
+        let file = demo_file();
+        let (code, numbers) = include(file.path(), &CodeInfo::parse(r#"file="demo.rs""#)).unwrap();
+        assert_eq!(code, "fn a() {}\n\n    fn main() {}\nfn b() {}\n");
+        assert_eq!(numbers, [1, 2, 4, 6]);
+    }
+
+    #[test]
+    fn include_invalid_selection() {
+        let file = demo_file();
+        let info = CodeInfo::parse(r#"file="demo.rs" lines="5-9""#);
+        assert!(matches!(
+            include(file.path(), &info),
+            Err(Error::InvalidLines(_, 5, 9))
+        ));
+        let info = CodeInfo::parse(r#"file="demo.rs" region="other""#);
+        assert!(matches!(
+            include(file.path(), &info),
+            Err(Error::MissingRegion(_, _))
+        ));
+    }
+}
@@ -1,6 +1,23 @@
+use std::path::Path;
+use std::{fs, process};
+
 use clap::ArgMatches;
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tempfile::TempDir;
+
+/// The config file that is loaded from the current directory if it exists (and no other file is
+/// set via `--config`).
+const DEFAULT_CONFIG_FILE: &str = "mdr.toml";
+
+/// The directory additional syntax definitions are loaded from if it exists (and no other
+/// directory is set in the config file); relative to the config file.
+const DEFAULT_SYNTAXES_DIR: &str = "syntaxes";
+
+/// The directory additional `.tmTheme` files are loaded from if it exists (and no other directory
+/// is set in the config file); relative to the config file.
+const DEFAULT_THEMES_DIR: &str = "themes";
 
 pub static CONFIG: Lazy<Config> = Lazy::new(|| {
     dotenv::dotenv().ok();
@@ -8,78 +25,486 @@ pub static CONFIG: Lazy<Config> = Lazy::new(|| {
     let matches = App::new("mdr")
         .version(env!("CARGO_PKG_VERSION"))
         .about("simple opinionated markdown renderer")
-        .arg(
-            Arg::with_name("SITE_NAME")
-                .long("name")
-                .help("the site's name")
-                .env("SITE_NAME")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("OUT_DIR")
-                .long("out")
-                .short("o")
-                .help("the directory the build result is saved to")
-                .env("OUT_DIR")
-                .takes_value(true)
-                .default_value("./out"),
-        )
-        .arg(
-            Arg::with_name("TWITTER_HANDLE")
-                .long("twitter")
-                .help("your Twitter handle")
-                .env("TWITTER_HANDLE")
-                .takes_value(true),
+        .args(&args())
+        .arg(file_arg())
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("builds the site and serves it locally, rebuilding it on changes")
+                .arg(file_arg())
+                .arg(
+                    Arg::with_name("PORT")
+                        .long("port")
+                        .short("p")
+                        .help("the port to listen on")
+                        .env("PORT")
+                        .takes_value(true)
+                        .default_value("8000")
+                        .validator(|v| {
+                            v.parse::<u16>()
+                                .map(|_| ())
+                                .map_err(|_| "must be a valid port".to_string())
+                        }),
+                ),
         )
-        .arg(
-            Arg::with_name("GITHUB_HANDLE")
-                .long("github")
-                .help("your Github handle")
-                .env("GITHUB_HANDLE")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("URL")
-                .long("url")
-                .help("the absolute URL of your site")
-                .env("URL")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("FILE")
-                .help("the markdown files to render")
-                .multiple(true)
-                .required(true),
+        .subcommand(
+            SubCommand::with_name("dump-syntaxes")
+                .about("compiles the syntax definitions of the syntaxes directory into a dump that is loaded faster"),
         )
         .get_matches();
 
-    Config(matches)
+    // options are global, i.e. they are also passed on to the subcommand's matches no matter on
+    // which side of the subcommand they are given, but files have to follow the subcommand
+    if matches.subcommand_name().is_some() && matches.is_present("FILE") {
+        eprintln!(
+            "error: files must be passed after the `{}` subcommand",
+            matches.subcommand_name().unwrap_or_default()
+        );
+        process::exit(1);
+    }
+    let (matches, serve, dump_syntaxes) = match matches.subcommand() {
+        ("serve", Some(matches)) => (matches.clone(), true, false),
+        ("dump-syntaxes", Some(matches)) => (matches.clone(), false, true),
+        _ => (matches, false, false),
+    };
+
+    let file = match load_file(&matches) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    };
+
+    // CLI args and env vars (both handled by clap) take precedence over the config file
+    let mut temp_dir = None;
+    let out_dir = match matches.value_of("OUT_DIR").or(file.out.as_deref()) {
+        Some(out_dir) => out_dir.to_string(),
+        // serve into a fresh temporary directory unless an out dir is explicitly configured
+        None if serve => match tempfile::Builder::new().prefix("mdr-serve-").tempdir() {
+            Ok(dir) => temp_dir.insert(dir).path().to_string_lossy().into_owned(),
+            Err(err) => {
+                eprintln!("error: failed to create temporary out dir: {}", err);
+                process::exit(1);
+            }
+        },
+        None => "./out".to_string(),
+    };
+
+    Config {
+        matches,
+        file,
+        out_dir,
+        _temp_dir: temp_dir,
+        serve,
+        dump_syntaxes,
+    }
 });
 
-pub struct Config(ArgMatches<'static>);
+/// The options shared by all subcommands.
+fn args() -> Vec<Arg<'static, 'static>> {
+    let args = vec![
+        Arg::with_name("CONFIG")
+            .long("config")
+            .short("c")
+            .help("the config file to load (defaults to ./mdr.toml if it exists)")
+            .env("MDR_CONFIG")
+            .takes_value(true),
+        Arg::with_name("SITE_NAME")
+            .long("name")
+            .help("the site's name")
+            .env("SITE_NAME")
+            .takes_value(true),
+        Arg::with_name("OUT_DIR")
+            .long("out")
+            .short("o")
+            .help("the directory the build result is saved to [default: ./out]")
+            .env("OUT_DIR")
+            .takes_value(true),
+        Arg::with_name("TWITTER_HANDLE")
+            .long("twitter")
+            .help("your Twitter handle")
+            .env("TWITTER_HANDLE")
+            .takes_value(true),
+        Arg::with_name("GITHUB_HANDLE")
+            .long("github")
+            .help("your Github handle")
+            .env("GITHUB_HANDLE")
+            .takes_value(true),
+        Arg::with_name("URL")
+            .long("url")
+            .help("the absolute URL of your site")
+            .env("URL")
+            .takes_value(true),
+        Arg::with_name("PER_PAGE")
+            .long("per-page")
+            .help("the number of posts per index page (all posts on one page if not set)")
+            .env("PER_PAGE")
+            .takes_value(true)
+            .validator(|v| match v.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(()),
+                _ => Err("must be a positive number".to_string()),
+            }),
+        Arg::with_name("DRAFTS")
+            .long("drafts")
+            .help("include posts marked as draft"),
+        Arg::with_name("FUTURE")
+            .long("future")
+            .help("include posts dated in the future"),
+        Arg::with_name("WATCH")
+            .long("watch")
+            .short("w")
+            .help("keep running and rebuild posts whenever they or their assets change"),
+        Arg::with_name("INCLUDE")
+            .long("include")
+            .help("only render files in content directories matching the glob")
+            .env("INCLUDE")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("EXCLUDE")
+            .long("exclude")
+            .help("skip files in content directories matching the glob")
+            .env("EXCLUDE")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+    ];
+    args.into_iter().map(|arg| arg.global(true)).collect()
+}
+
+fn file_arg() -> Arg<'static, 'static> {
+    Arg::with_name("FILE")
+        .help("the markdown files or content directories to render (defaults to `content` in mdr.toml)")
+        .multiple(true)
+}
+
+/// Loads the config file set via `--config`, or `mdr.toml` in the current directory if it exists.
+/// Relative paths in the config file are resolved relative to the file's directory.
+fn load_file(matches: &ArgMatches) -> Result<ConfigFile, String> {
+    let path = match matches.value_of("CONFIG") {
+        Some(path) => Path::new(path),
+        None if Path::new(DEFAULT_CONFIG_FILE).is_file() => Path::new(DEFAULT_CONFIG_FILE),
+        None => {
+            return Ok(ConfigFile {
+                syntaxes: default_dir(Path::new(""), DEFAULT_SYNTAXES_DIR),
+                themes: default_dir(Path::new(""), DEFAULT_THEMES_DIR),
+                ..ConfigFile::default()
+            })
+        }
+    };
+
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let mut file: ConfigFile =
+        toml::from_str(&content).map_err(|err| format!("invalid {}: {}", path.display(), err))?;
+
+    if file.per_page == Some(0) {
+        return Err(format!(
+            "invalid {}: per_page must be a positive number",
+            path.display()
+        ));
+    }
+
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let resolve = |p: &mut String| *p = base.join(&*p).to_string_lossy().into_owned();
+    if let Some(out) = &mut file.out {
+        resolve(out);
+    }
+    file.content.iter_mut().for_each(resolve);
+    match &mut file.syntaxes {
+        Some(syntaxes) => resolve(syntaxes),
+        None => file.syntaxes = default_dir(base, DEFAULT_SYNTAXES_DIR),
+    }
+    match &mut file.themes {
+        Some(themes) => resolve(themes),
+        None => file.themes = default_dir(base, DEFAULT_THEMES_DIR),
+    }
+
+    Ok(file)
+}
+
+fn default_dir(base: &Path, name: &str) -> Option<String> {
+    let dir = base.join(name);
+    dir.is_dir().then(|| dir.to_string_lossy().into_owned())
+}
+
+/// The contents of `mdr.toml`. Every option is optional; unknown keys are rejected to catch typos.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    name: Option<String>,
+    url: Option<String>,
+    out: Option<String>,
+    per_page: Option<usize>,
+    content: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    /// Whether to include posts marked as draft.
+    drafts: bool,
+    /// Whether to include posts dated in the future.
+    future: bool,
+    /// A directory of additional `.sublime-syntax` files used for highlighting code.
+    syntaxes: Option<String>,
+    /// A directory of additional `.tmTheme` files that can be selected as highlighting theme.
+    themes: Option<String>,
+    social: SocialConfig,
+    feed: FeedConfig,
+    taxonomies: TaxonomiesConfig,
+    markdown: MarkdownConfig,
+    toc: TocConfig,
+    highlight: HighlightConfig,
+    images: ImagesConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SocialConfig {
+    twitter: Option<String>,
+    github: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    /// Whether to write `feed.xml` (only possible if the site's URL is set).
+    pub enabled: bool,
+    /// The maximum number of posts in the feed.
+    pub limit: Option<usize>,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            limit: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaxonomiesConfig {
+    pub categories: bool,
+    pub tags: bool,
+}
+
+impl Default for TaxonomiesConfig {
+    fn default() -> Self {
+        Self {
+            categories: true,
+            tags: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownConfig {
+    pub strikethrough: bool,
+    pub tables: bool,
+    pub footnotes: bool,
+    pub tasklists: bool,
+    /// Converts quotes, dashes and ellipses into their typographic counterparts.
+    pub smart_punctuation: bool,
+    /// Renders footnotes as sidenotes in the margin instead of at the end of the post.
+    pub sidenotes: bool,
+    /// Renders `$...$` and `$$...$$` math as MathML.
+    pub math: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            strikethrough: true,
+            tables: true,
+            footnotes: true,
+            tasklists: true,
+            smart_punctuation: false,
+            sidenotes: false,
+            math: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TocConfig {
+    /// Whether to add a table of contents to posts without a `[[toc]]` marker.
+    pub auto: bool,
+    /// The number of headings (excluding the title) a post needs to automatically get a table of
+    /// contents.
+    pub min_headings: usize,
+}
+
+impl Default for TocConfig {
+    fn default() -> Self {
+        Self {
+            auto: true,
+            min_headings: 4,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightConfig {
+    /// The syntect theme (or name of a `.tmTheme` file in the themes directory) to generate the
+    /// highlighting CSS from; the bundled highlighting CSS is used if not set.
+    pub theme: Option<String>,
+    /// The theme used instead if the reader prefers a dark color scheme.
+    pub dark_theme: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// The widths (in pixels) local PNG and JPEG images are resized to for their `srcset`; widths
+    /// larger than an image are skipped.
+    pub widths: Vec<u32>,
+    /// The `sizes` attribute of resized images.
+    pub sizes: String,
+    /// Whether to additionally generate (lossless) WebP variants of PNG images, offered via a
+    /// `<picture>` element.
+    pub webp: bool,
+    /// Whether to render images with a title that are alone in a paragraph as figures.
+    pub figures: bool,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            widths: vec![480, 960, 1440],
+            sizes: "(max-width: 750px) 100vw, 750px".to_string(),
+            webp: false,
+            figures: true,
+        }
+    }
+}
+
+pub struct Config {
+    matches: ArgMatches<'static>,
+    file: ConfigFile,
+    out_dir: String,
+    /// Keeps the temporary out dir (when serving without a configured out dir) alive.
+    _temp_dir: Option<TempDir>,
+    serve: bool,
+    dump_syntaxes: bool,
+}
 
 impl Config {
     pub fn site_name(&self) -> &str {
-        self.0.value_of("SITE_NAME").unwrap_or("Blog")
+        self.matches
+            .value_of("SITE_NAME")
+            .or(self.file.name.as_deref())
+            .unwrap_or("Blog")
     }
 
     pub fn out_dir(&self) -> &str {
-        self.0.value_of("OUT_DIR").unwrap_or("./out")
+        &self.out_dir
     }
 
     pub fn twitter_handle(&self) -> Option<&str> {
-        self.0.value_of("TWITTER_HANDLE")
+        self.matches
+            .value_of("TWITTER_HANDLE")
+            .or(self.file.social.twitter.as_deref())
     }
 
     pub fn github_handle(&self) -> Option<&str> {
-        self.0.value_of("GITHUB_HANDLE")
+        self.matches
+            .value_of("GITHUB_HANDLE")
+            .or(self.file.social.github.as_deref())
     }
 
     pub fn url(&self) -> Option<&str> {
-        self.0.value_of("URL")
+        self.matches.value_of("URL").or(self.file.url.as_deref())
+    }
+
+    pub fn per_page(&self) -> Option<usize> {
+        self.matches
+            .value_of("PER_PAGE")
+            .and_then(|v| v.parse().ok())
+            .or(self.file.per_page)
+    }
+
+    pub fn drafts(&self) -> bool {
+        self.matches.is_present("DRAFTS") || self.file.drafts
+    }
+
+    pub fn future(&self) -> bool {
+        self.matches.is_present("FUTURE") || self.file.future
+    }
+
+    pub fn watch(&self) -> bool {
+        self.serve || self.matches.is_present("WATCH")
+    }
+
+    /// Whether the site is served locally via `mdr serve`.
+    pub fn serve(&self) -> bool {
+        self.serve
+    }
+
+    /// Whether to only compile the syntaxes directory into a dump via `mdr dump-syntaxes`.
+    pub fn dump_syntaxes(&self) -> bool {
+        self.dump_syntaxes
+    }
+
+    pub fn port(&self) -> u16 {
+        self.matches
+            .value_of("PORT")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(8000)
+    }
+
+    pub fn include(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self.matches.values_of("INCLUDE") {
+            Some(values) => Box::new(values),
+            None => Box::new(self.file.include.iter().map(String::as_str)),
+        }
+    }
+
+    pub fn exclude(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self.matches.values_of("EXCLUDE") {
+            Some(values) => Box::new(values),
+            None => Box::new(self.file.exclude.iter().map(String::as_str)),
+        }
+    }
+
+    pub fn files(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self.matches.values_of("FILE") {
+            Some(values) => Box::new(values),
+            None => Box::new(self.file.content.iter().map(String::as_str)),
+        }
+    }
+
+    pub fn syntaxes(&self) -> Option<&Path> {
+        self.file.syntaxes.as_deref().map(Path::new)
+    }
+
+    pub fn themes(&self) -> Option<&Path> {
+        self.file.themes.as_deref().map(Path::new)
+    }
+
+    pub fn feed(&self) -> &FeedConfig {
+        &self.file.feed
+    }
+
+    pub fn taxonomies(&self) -> &TaxonomiesConfig {
+        &self.file.taxonomies
+    }
+
+    pub fn markdown(&self) -> &MarkdownConfig {
+        &self.file.markdown
+    }
+
+    pub fn toc(&self) -> &TocConfig {
+        &self.file.toc
+    }
+
+    pub fn highlight(&self) -> &HighlightConfig {
+        &self.file.highlight
     }
 
-    pub fn files(&self) -> impl Iterator<Item = &str> {
-        self.0.values_of("FILE").unwrap()
+    pub fn images(&self) -> &ImagesConfig {
+        &self.file.images
     }
 }
@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+
+use super::Error;
+use crate::config::CONFIG;
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+
+/// Name of the gitignore-style files that exclude paths from the discovery in content directories.
+const IGNORE_FILE: &str = ".mdrignore";
+
+/// Collects the markdown files to render from the paths given on the command line. Directories are
+/// searched recursively for `.md` files (skipping hidden files, paths listed in `.mdrignore` files
+/// and paths not matching the `--include`/`--exclude` globs). Files that are passed explicitly must
+/// be markdown files.
+pub fn markdown_files() -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    let mut paths = CONFIG.files().peekable();
+    if paths.peek().is_none() {
+        return Err(Error::MissingInput);
+    }
+
+    for path in paths {
+        let path = PathBuf::from(path);
+
+        if path.is_dir() {
+            discover(&path, &mut files)?;
+            continue;
+        }
+
+        if !path.is_file() || !is_markdown(&path) {
+            return Err(Error::NonMarkdownFile(path));
+        }
+
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+fn discover(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    // globs are matched relative to the content directory
+    let mut overrides = OverrideBuilder::new(dir);
+    for glob in CONFIG.include() {
+        overrides.add(glob)?;
+    }
+    for glob in CONFIG.exclude() {
+        overrides.add(&format!("!{}", glob))?;
+    }
+
+    let walker = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .hidden(true)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .overrides(overrides.build()?)
+        .sort_by_file_path(Path::cmp)
+        .build();
+    for entry in walker {
+        let entry = entry?;
+        let is_file = entry.file_type().map(|t| t.is_file()).unwrap_or(false);
+        if is_file && is_markdown(entry.path()) && !files.iter().any(|f| f == entry.path()) {
+            files.push(entry.into_path());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("md"))
+        .unwrap_or(false)
+}
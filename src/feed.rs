@@ -7,7 +7,16 @@ use xml::writer::events::XmlEvent;
 use xml::writer::EventWriter;
 use xml::EmitterConfig;
 
-pub fn create(posts: &[Post]) -> Result<(), Error> {
+/// Whether `feed.xml` is written; requires the site's URL to be set.
+pub fn is_enabled() -> bool {
+    CONFIG.url().is_some() && CONFIG.feed().enabled
+}
+
+pub fn create(posts: &[&Post]) -> Result<(), Error> {
+    if !is_enabled() {
+        return Ok(());
+    }
+
     let url = match CONFIG.url() {
         Some(url) => {
             if url.ends_with('/') && !url.is_empty() {
@@ -63,9 +72,9 @@ pub fn create(posts: &[Post]) -> Result<(), Error> {
     wr.write(XmlEvent::end_element())?;
 
     // updated
-    if let Some(post) = posts.first() {
+    if let Some(updated_at) = posts.iter().map(|post| post.updated_at()).max() {
         wr.write(XmlEvent::start_element("updated"))?;
-        wr.write(XmlEvent::characters(&post.created_at.to_rfc3339()))?;
+        wr.write(XmlEvent::characters(&updated_at.to_rfc3339()))?;
         wr.write(XmlEvent::end_element())?;
     }
 
@@ -76,7 +85,10 @@ pub fn create(posts: &[Post]) -> Result<(), Error> {
     wr.write(XmlEvent::end_element())?;
     wr.write(XmlEvent::end_element())?;
 
-    for post in posts {
+    for post in posts
+        .iter()
+        .take(CONFIG.feed().limit.unwrap_or(posts.len()))
+    {
         wr.write(XmlEvent::start_element("entry"))?;
 
         // title
@@ -94,11 +106,29 @@ pub fn create(posts: &[Post]) -> Result<(), Error> {
         wr.write(XmlEvent::characters(&post_url))?;
         wr.write(XmlEvent::end_element())?;
 
+        // published
+        wr.write(XmlEvent::start_element("published"))?;
+        wr.write(XmlEvent::characters(&post.created_at.to_rfc3339()))?;
+        wr.write(XmlEvent::end_element())?;
+
         // updated
         wr.write(XmlEvent::start_element("updated"))?;
-        wr.write(XmlEvent::characters(&post.created_at.to_rfc3339()))?;
+        wr.write(XmlEvent::characters(&post.updated_at().to_rfc3339()))?;
         wr.write(XmlEvent::end_element())?;
 
+        // summary
+        if let Some(description) = &post.description {
+            wr.write(XmlEvent::start_element("summary"))?;
+            wr.write(XmlEvent::characters(description))?;
+            wr.write(XmlEvent::end_element())?;
+        }
+
+        // categories
+        for tag in post.categories.iter().chain(&post.tags) {
+            wr.write(XmlEvent::start_element("category").attr("term", tag))?;
+            wr.write(XmlEvent::end_element())?;
+        }
+
         // content
         wr.write(XmlEvent::start_element("content").attr("type", "html"))?;
         wr.write(XmlEvent::CData(&post.content))?;
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::path::Path;
+
+use super::Error;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+
+/// The typed fields that can be set in a `---` (YAML) or `+++` (TOML) block at the very top of a
+/// markdown file.
+#[derive(Debug, Default, Deserialize)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub date: Option<Date>,
+    pub updated: Option<Date>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub draft: bool,
+    pub slug: Option<String>,
+    pub layout: Option<String>,
+    /// Forces the table of contents on or off for the post.
+    pub toc: Option<bool>,
+    /// Overrides the site's markdown extensions for the post.
+    #[serde(default)]
+    pub markdown: MarkdownOptions,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarkdownOptions {
+    pub strikethrough: Option<bool>,
+    pub tables: Option<bool>,
+    pub footnotes: Option<bool>,
+    pub tasklists: Option<bool>,
+    pub smart_punctuation: Option<bool>,
+    pub sidenotes: Option<bool>,
+    pub math: Option<bool>,
+}
+
+/// Splits the front matter from the markdown content. Files without front matter result in the
+/// default (empty) front matter and the unchanged input. As `---` is also a thematic break, a `---`
+/// block only counts as front matter if it is closed and contains YAML key-value pairs.
+pub fn parse<'a>(path: &Path, input: &'a str) -> Result<(FrontMatter, &'a str), Error> {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+
+    let delimiter = match first_line(input) {
+        "---" => "---",
+        "+++" => "+++",
+        _ => return Ok((FrontMatter::default(), input)),
+    };
+
+    // skip the opening delimiter line
+    let start = input.find('\n').map(|i| i + 1).unwrap_or(input.len());
+    let mut offset = start;
+    for line in input[start..].split_inclusive('\n') {
+        let end = offset + line.len();
+        if line.trim_end() == delimiter {
+            let raw = &input[start..offset];
+            let content = &input[end..];
+            let front_matter = if delimiter == "---" {
+                if raw.trim().is_empty() {
+                    FrontMatter::default()
+                } else {
+                    match serde_yaml::from_str(raw) {
+                        Ok(front_matter) => front_matter,
+                        // report invalid fields, but keep other content (e.g. text between two
+                        // thematic breaks) as part of the post
+                        Err(err) if is_yaml_mapping(raw) => {
+                            return Err(Error::Yaml(path.to_path_buf(), err))
+                        }
+                        Err(_) => return Ok((FrontMatter::default(), input)),
+                    }
+                }
+            } else {
+                toml::from_str(raw).map_err(|err| Error::Toml(path.to_path_buf(), err))?
+            };
+            return Ok((front_matter, content));
+        }
+        offset = end;
+    }
+
+    // not closed, so the first line is just part of the post
+    Ok((FrontMatter::default(), input))
+}
+
+fn is_yaml_mapping(raw: &str) -> bool {
+    matches!(
+        serde_yaml::from_str::<serde_yaml::Value>(raw),
+        Ok(serde_yaml::Value::Mapping(_))
+    )
+}
+
+fn first_line(input: &str) -> &str {
+    input.lines().next().unwrap_or_default().trim_end()
+}
+
+/// Parses the date formats supported in front matter and `<meta name="date" />` tags: RFC 3339,
+/// RFC 2822, `YYYY-MM-DD HH:MM:SS` (interpreted as UTC) and `YYYY-MM-DD`.
+pub fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    DateTime::parse_from_rfc3339(s)
+        .or_else(|_| DateTime::parse_from_rfc2822(s))
+        .map(|dt| DateTime::<Utc>::from_utc(dt.naive_utc(), Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(s, "%F %T")
+                .or_else(|_| NaiveDateTime::parse_from_str(s, "%FT%T"))
+                .ok()
+                .or_else(|| {
+                    NaiveDate::parse_from_str(s, "%F")
+                        .ok()
+                        .map(|date| date.and_hms(0, 0, 0))
+                })
+                .map(|dt| DateTime::<Utc>::from_utc(dt, Utc))
+        })
+}
+
+/// A date in the front matter. Accepts strings in any of the formats supported by [`parse_date`]
+/// as well as native TOML datetimes.
+#[derive(Debug, Clone, Copy)]
+pub struct Date(pub DateTime<Utc>);
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct DateVisitor;
+
+        impl<'de> Visitor<'de> for DateVisitor {
+            type Value = Date;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a date (e.g. 2022-01-24 or 2022-01-24T10:00:00Z)")
+            }
+
+            fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                parse_date(s)
+                    .map(Date)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(s), &self))
+            }
+
+            // TOML datetimes are deserialized as a map with a single private key
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let dt =
+                    toml::value::Datetime::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                self.visit_str(&dt.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(DateVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<(FrontMatter, &str), Error> {
+        super::parse(Path::new("post.md"), input)
+    }
+
+    #[test]
+    fn yaml_front_matter() {
+        let (front_matter, content) = parse("---\ntitle: Hello\ndraft: true\n---\n# Hi\n").unwrap();
+        assert_eq!(front_matter.title.as_deref(), Some("Hello"));
+        assert!(front_matter.draft);
+        assert_eq!(content, "# Hi\n");
+    }
+
+    #[test]
+    fn toml_front_matter() {
+        let (front_matter, content) = parse("+++\ntags = [\"a\"]\n+++\ntext\n").unwrap();
+        assert_eq!(front_matter.tags, ["a"]);
+        assert_eq!(content, "text\n");
+    }
+
+    #[test]
+    fn unclosed_thematic_break_is_content() {
+        let input = "---\n\nSome text.\n";
+        let (front_matter, content) = parse(input).unwrap();
+        assert!(front_matter.title.is_none());
+        assert_eq!(content, input);
+    }
+
+    #[test]
+    fn text_between_thematic_breaks_is_content() {
+        let input = "---\nSome text.\n---\nMore text.\n";
+        let (_, content) = parse(input).unwrap();
+        assert_eq!(content, input);
+    }
+
+    #[test]
+    fn invalid_fields_are_reported() {
+        assert!(matches!(
+            parse("---\ndate: tomorrow\n---\n"),
+            Err(Error::Yaml(_, _))
+        ));
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use super::{content_hash, Error};
+use crate::config::CONFIG;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageError, ImageFormat, ImageReader};
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+
+const JPEG_QUALITY: u8 = 85;
+
+/// The `srcset`s of an image's resized variants.
+pub struct Srcset {
+    /// The variants in the image's own format, including the original image.
+    pub original: String,
+    /// The WebP variants, if enabled and the image is a PNG.
+    pub webp: Option<String>,
+    /// The file names of all generated variants.
+    pub variants: Vec<String>,
+}
+
+/// Resizes the PNG or JPEG image at `path` (written to the out dir as `file_name`) to the
+/// configured widths. Variants are named after the image's content hash and only generated if
+/// they don't exist in the out dir yet. Returns `None` for other formats and for images that don't
+/// need any variants.
+pub fn srcset(path: &Path, content: &[u8], file_name: &str) -> Result<Option<Srcset>, Error> {
+    let config = CONFIG.images();
+    let format = match image::guess_format(content) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => format,
+        _ => return Ok(None),
+    };
+
+    let err = |err| Error::Image(path.to_path_buf(), err);
+    let (width, _) = ImageReader::with_format(Cursor::new(content), format)
+        .into_dimensions()
+        .map_err(err)?;
+    let mut widths = config
+        .widths
+        .iter()
+        .copied()
+        .filter(|w| *w < width)
+        .collect::<Vec<_>>();
+    widths.sort_unstable();
+    widths.dedup();
+    // WebP variants are encoded losslessly, which only pays off for PNGs: they are usually much
+    // larger than the JPEG they are created from
+    let webp = config.webp && format == ImageFormat::Png;
+    if widths.is_empty() && !webp {
+        return Ok(None);
+    }
+
+    let stem = path.file_stem().and_then(|n| n.to_str()).unwrap_or("image");
+    let hash = content_hash(content);
+    // only decoded once a variant actually has to be generated
+    let mut decoded = None;
+    let mut variants = Vec::new();
+    let mut variant = |width: u32, format: ImageFormat| -> Result<String, Error> {
+        let ext = format.extensions_str()[0];
+        let name = format!("{}-{}-{}w.{}", stem, hash, width, ext);
+        let out_path = Path::new(CONFIG.out_dir()).join(&name);
+        if !out_path.is_file() {
+            let image = match &mut decoded {
+                Some(image) => image,
+                None => decoded.insert(image::load_from_memory(content).map_err(err)?),
+            };
+            let resized;
+            let image = if width < image.width() {
+                resized = image.resize(width, u32::MAX, FilterType::Lanczos3);
+                &resized
+            } else {
+                &*image
+            };
+            std::fs::write(out_path, encode(image, format).map_err(err)?)?;
+        }
+        let candidate = format!("{} {}w", name, width);
+        variants.push(name);
+        Ok(candidate)
+    };
+
+    let mut original = Vec::new();
+    for w in &widths {
+        original.push(variant(*w, format)?);
+    }
+    original.push(format!("{} {}w", file_name, width));
+
+    let webp = if webp {
+        let mut webp = Vec::new();
+        for w in widths.iter().copied().chain([width]) {
+            webp.push(variant(w, ImageFormat::WebP)?);
+        }
+        Some(webp.join(", "))
+    } else {
+        None
+    };
+
+    Ok(Some(Srcset {
+        original: original.join(", "),
+        webp,
+        variants,
+    }))
+}
+
+/// Reads the intrinsic size of a PNG, JPEG, GIF, WebP or SVG image. The size of an SVG is taken
+/// from the `width` and `height` attributes of its root element, or its `viewBox`.
+pub fn dimensions(content: &[u8]) -> Option<(u32, u32)> {
+    match ImageReader::new(Cursor::new(content))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+    {
+        Ok(size) => Some(size),
+        Err(_) => svg_dimensions(content),
+    }
+}
+
+fn svg_dimensions(content: &[u8]) -> Option<(u32, u32)> {
+    let svg = std::str::from_utf8(content).ok()?;
+    let mut size = None;
+    let mut is_root = true;
+    rewrite_str(
+        svg,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("svg", |el| {
+                if !is_root {
+                    return Ok(());
+                }
+                is_root = false;
+
+                let length = |name| el.get_attribute(name).and_then(|v| parse_length(&v));
+                size = match (length("width"), length("height")) {
+                    (Some(width), Some(height)) => Some((width, height)),
+                    _ => el.get_attribute("viewBox").and_then(|view_box| {
+                        let values = view_box
+                            .split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|v| !v.is_empty())
+                            .map(|v| v.parse::<f64>().ok())
+                            .collect::<Option<Vec<_>>>()?;
+                        match values[..] {
+                            [_, _, width, height] if width > 0.0 && height > 0.0 => {
+                                Some((width.round() as u32, height.round() as u32))
+                            }
+                            _ => None,
+                        }
+                    }),
+                };
+                Ok(())
+            })],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .ok()?;
+    size
+}
+
+/// Parses an SVG length in pixels (e.g. `100` or `100px`); relative units are not supported.
+fn parse_length(value: &str) -> Option<u32> {
+    let value = value.trim();
+    let value = value.strip_suffix("px").unwrap_or(value);
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|v| *v > 0.0)
+        .map(|v| v.round() as u32)
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, ImageError> {
+    let mut buf = Vec::new();
+    match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY))?,
+        ImageFormat::WebP => DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(&mut buf))?,
+        format => image.write_to(&mut Cursor::new(&mut buf), format)?,
+    }
+    Ok(buf)
+}
@@ -1,28 +1,510 @@
+mod assets;
+mod code;
 mod config;
+mod discover;
 mod feed;
+mod front_matter;
+mod images;
+mod math;
+mod serve;
+mod taxonomy;
+mod toc;
 mod transform;
+mod watch;
 
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 
+use crate::assets::Asset;
 use crate::config::CONFIG;
+use crate::math::Math;
 use crate::transform::Transformer;
 use chrono::{DateTime, NaiveDate, Utc};
-use lol_html::html_content::ContentType;
+use front_matter::FrontMatter;
+use lol_html::errors::RewritingError;
+use lol_html::html_content::{ContentType, Element};
 use lol_html::{element, rewrite_str, ElementContentHandlers, RewriteStrSettings, Selector};
 use pulldown_cmark::{html, Options, Parser};
 use sha2::{Digest, Sha256};
-use url::Url;
 
 fn main() -> Result<(), Error> {
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_STRIKETHROUGH);
+    if CONFIG.dump_syntaxes() {
+        return code::dump_syntaxes();
+    }
+
+    // bind the port before writing anything, so that a second server can't touch the out dir of
+    // the one already listening
+    if CONFIG.serve() {
+        serve::start()?;
+    }
 
     let out_dir = CONFIG.out_dir();
     fs::create_dir_all(out_dir)?;
 
-    // prepare layout
+    let layout = prepare_layout(include_str!("theme/layout.html"))?;
+
+    // write posts
+    let paths = discover::markdown_files()?;
+    let pages = page_file_names(&paths);
+    let mut posts = Vec::new();
+    for path in &paths {
+        posts.push(render_post(&layout, path, &pages)?);
+    }
+    check_links(&posts)?;
+
+    create_listings(&layout, &mut posts)?;
+
+    if CONFIG.serve() {
+        watch::run(&layout, posts, serve::reload)?;
+    } else if CONFIG.watch() {
+        watch::run(&layout, posts, || {})?;
+    }
+
+    Ok(())
+}
+
+/// Renders the markdown file at `path` and writes the resulting page to the out dir. `pages` maps
+/// the (canonical) paths of all markdown files of the site to the file names of their pages, to
+/// rewrite links between posts.
+fn render_post(layout: &str, path: &Path, pages: &HashMap<PathBuf, String>) -> Result<Post, Error> {
+    // files (other than the markdown file itself) the rendered page depends on
+    let dependencies = RefCell::new(Vec::new());
+
+    let input = fs::read_to_string(path)?;
+    let (front_matter, markdown) = front_matter::parse(path, &input)?;
+
+    // markdown extensions as configured for the site, overridden by the post's front matter
+    let site = CONFIG.markdown();
+    let post = &front_matter.markdown;
+    let mut options = Options::empty();
+    for (option, enabled) in [
+        (
+            Options::ENABLE_STRIKETHROUGH,
+            post.strikethrough.unwrap_or(site.strikethrough),
+        ),
+        (Options::ENABLE_TABLES, post.tables.unwrap_or(site.tables)),
+        (
+            Options::ENABLE_FOOTNOTES,
+            post.footnotes.unwrap_or(site.footnotes),
+        ),
+        (
+            Options::ENABLE_TASKLISTS,
+            post.tasklists.unwrap_or(site.tasklists),
+        ),
+        (
+            Options::ENABLE_SMART_PUNCTUATION,
+            post.smart_punctuation.unwrap_or(site.smart_punctuation),
+        ),
+    ] {
+        options.set(option, enabled);
+    }
+
+    let parser = Parser::new_ext(markdown, options).into_offset_iter();
+    let math = Math::new(markdown, parser, post.math.unwrap_or(site.math));
+    let mut events = Transformer::new(math)
+        .sidenotes(post.sidenotes.unwrap_or(site.sidenotes))
+        .figures(CONFIG.images().figures)
+        .base_dir(path.parent().unwrap_or_else(|| Path::new("")));
+    let mut content = String::new();
+    html::push_html(&mut content, &mut events);
+    if let Some(err) = events.take_error() {
+        return Err(err);
+    }
+    dependencies
+        .borrow_mut()
+        .extend(events.includes().iter().cloned());
+    let content = toc::insert(
+        content,
+        events.headings(),
+        events.has_toc_marker(),
+        front_matter.toc,
+    )?;
+
+    let title = front_matter
+        .title
+        .as_deref()
+        .or_else(|| events.title())
+        .map(String::from);
+    // look for `<meta name="date" content="" />` to extract the posts creation date (only if
+    // there is no date in the front matter)
+    let mut created_at = front_matter.date.map(|date| date.0);
+    if created_at.is_none() && !events.meta().is_empty() {
+        rewrite_str(
+            events.meta(),
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("meta[name=date]", |el| {
+                    created_at = el
+                        .get_attribute("content")
+                        .and_then(|content| front_matter::parse_date(&content));
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )?;
+    }
+
+    let mut out_path = PathBuf::new();
+    out_path.push(CONFIG.out_dir());
+    let file_name = page_file_name(path, front_matter.slug.as_deref())?;
+    if created_at.is_none() {
+        created_at =
+            file_name_date(path).map(|date| DateTime::<Utc>::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+
+    let created_at = created_at.ok_or_else(|| Error::MissingDate(path.to_path_buf()))?;
+
+    let draft = front_matter.draft;
+    let scheduled = created_at > Utc::now();
+    let published = is_published(draft, created_at);
+    let notice = if draft {
+        Some(r#"<p class="notice">This post is a draft.</p>"#.to_string())
+    } else if scheduled {
+        Some(format!(
+            r#"<p class="notice">This post is scheduled for {}.</p>"#,
+            created_at.format("%F")
+        ))
+    } else {
+        None
+    };
+
+    // drafts and scheduled posts that aren't written must not leak their assets to the out dir
+    let (content, references) = if published {
+        rewrite_assets(path, &content, pages)?
+    } else {
+        (content, References::default())
+    };
+    dependencies.borrow_mut().extend(references.dependencies);
+
+    // a custom layout is resolved relative to the post's markdown file
+    let custom_layout = match &front_matter.layout {
+        Some(layout) => {
+            let layout_path = path.parent().unwrap_or_else(|| Path::new("")).join(layout);
+            if !layout_path.is_file() {
+                return Err(Error::MissingLayout(layout_path));
+            }
+            let custom_layout = prepare_layout(&fs::read_to_string(&layout_path)?)?;
+            dependencies.borrow_mut().push(layout_path);
+            Some(custom_layout)
+        }
+        None => None,
+    };
+
+    let html = create_page(
+        custom_layout.as_deref().unwrap_or(layout),
+        &content,
+        vec![
+            element!("[role=main]", |el| {
+                if let Some(notice) = &notice {
+                    el.prepend(notice, ContentType::Html);
+                }
+
+                let links = taxonomy::render_links(&front_matter.categories, &front_matter.tags);
+                if !links.is_empty() {
+                    el.append(&links, ContentType::Html);
+                }
+                Ok(())
+            }),
+            element!("title", |el| {
+                if !events.meta().is_empty() {
+                    el.after(events.meta(), ContentType::Html);
+                }
+
+                if let Some(description) = &front_matter.description {
+                    el.after(
+                        &format!(
+                            "\n<meta name=\"description\" content=\"{}\" />",
+                            escape_attr(description)
+                        ),
+                        ContentType::Html,
+                    );
+                }
+
+                // keep search engines from indexing drafts
+                if front_matter.draft {
+                    el.after(
+                        "\n<meta name=\"robots\" content=\"noindex\" />",
+                        ContentType::Html,
+                    );
+                }
+
+                if let Some(title) = &title {
+                    el.set_inner_content(
+                        &format!("{} - {}", title, CONFIG.site_name()),
+                        ContentType::Text,
+                    );
+                }
+
+                Ok(())
+            }),
+        ],
+    )?;
+
+    let FrontMatter {
+        updated,
+        categories,
+        tags,
+        description,
+        ..
+    } = front_matter;
+    let post = Post {
+        source: path.to_path_buf(),
+        dependencies: dependencies.into_inner(),
+        assets: references.assets,
+        links: references.links,
+        file_name,
+        title: title.unwrap_or_default(),
+        content,
+        created_at,
+        updated_at: updated.map(|date| date.0),
+        description,
+        categories,
+        tags,
+        draft,
+    };
+
+    // drafts and scheduled posts are only written if explicitly requested
+    if published {
+        out_path.push(&*post.file_name);
+        fs::write(out_path, html)?;
+    }
+
+    Ok(post)
+}
+
+/// Whether a post is part of this build: drafts and scheduled posts are only published if
+/// explicitly requested.
+fn is_published(draft: bool, created_at: DateTime<Utc>) -> bool {
+    (!draft || CONFIG.drafts()) && (created_at <= Utc::now() || CONFIG.future())
+}
+
+/// The local files a post references, collected while rewriting their URLs.
+#[derive(Default)]
+struct References {
+    /// The file names of the assets written to the out dir, with the files they were created from.
+    assets: Vec<(String, PathBuf)>,
+    /// The canonical paths of the linked posts.
+    links: Vec<PathBuf>,
+    /// All referenced files, i.e. assets and linked posts.
+    dependencies: Vec<PathBuf>,
+}
+
+/// Writes the local files the rendered post references to the out dir and rewrites their URLs
+/// (relative to the post's markdown file at `path`) accordingly. Links to other posts are rewritten
+/// to their pages, which are looked up in `pages`.
+fn rewrite_assets(
+    path: &Path,
+    content: &str,
+    pages: &HashMap<PathBuf, String>,
+) -> Result<(String, References), Error> {
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let references = RefCell::new(References::default());
+    let collect = |asset: &Asset| {
+        let mut references = references.borrow_mut();
+        references
+            .assets
+            .push((asset.file_name.clone(), asset.path.clone()));
+        references.dependencies.push(asset.path.clone());
+    };
+    let rewrite_url = |el: &mut Element, attr: &str| -> HandlerResult {
+        if let Some(url) = el.get_attribute(attr) {
+            if let Some(asset) = assets::resolve(base, &url)? {
+                el.set_attribute(attr, &asset.url)?;
+                collect(&asset);
+            }
+        }
+        Ok(())
+    };
+    let content = rewrite_str(
+        content,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("a[href]", |el| {
+                    // links to other posts point to their pages
+                    if let Some(href) = el.get_attribute("href") {
+                        if let Some((url, target)) = assets::resolve_page(base, &href, pages)
+                            .map_err(|target| Error::UnknownPage(path.to_path_buf(), target))?
+                        {
+                            el.set_attribute("href", &url)?;
+                            let mut references = references.borrow_mut();
+                            references.links.push(target.clone());
+                            references.dependencies.push(target);
+                            return Ok(());
+                        }
+                    }
+                    rewrite_url(el, "href")
+                }),
+                element!("video[src], audio[src], source[src]", |el| {
+                    rewrite_url(el, "src")
+                }),
+                element!("video[poster]", |el| rewrite_url(el, "poster")),
+                element!("object[data]", |el| rewrite_url(el, "data")),
+                // before handling images, so that their srcset is known to be set by the author
+                element!("img[srcset], source[srcset]", |el| {
+                    if let Some(srcset) = el.get_attribute("srcset") {
+                        let (srcset, srcset_assets) = assets::resolve_srcset(base, &srcset)?;
+                        el.set_attribute("srcset", &srcset)?;
+                        srcset_assets.iter().for_each(collect);
+                    }
+                    Ok(())
+                }),
+                element!("img[src]", |el| {
+                    let asset = match el
+                        .get_attribute("src")
+                        .map(|url| assets::resolve(base, &url))
+                        .transpose()?
+                        .flatten()
+                    {
+                        Some(asset) => asset,
+                        None => return Ok(()),
+                    };
+                    el.set_attribute("src", &asset.url)?;
+                    collect(&asset);
+
+                    // reserve the image's space to avoid layout shifts once it is loaded
+                    if !el.has_attribute("width") && !el.has_attribute("height") {
+                        if let Some((width, height)) = images::dimensions(&asset.content) {
+                            el.set_attribute("width", &width.to_string())?;
+                            el.set_attribute("height", &height.to_string())?;
+                        }
+                    }
+                    for (name, value) in [("loading", "lazy"), ("decoding", "async")] {
+                        if !el.has_attribute(name) {
+                            el.set_attribute(name, value)?;
+                        }
+                    }
+
+                    // offer resized variants, unless the author already set a srcset
+                    if !el.has_attribute("srcset") {
+                        if let Some(srcset) =
+                            images::srcset(&asset.path, &asset.content, &asset.file_name)?
+                        {
+                            let sizes = el
+                                .get_attribute("sizes")
+                                .unwrap_or_else(|| CONFIG.images().sizes.clone());
+                            el.set_attribute("srcset", &srcset.original)?;
+                            el.set_attribute("sizes", &sizes)?;
+                            if let Some(webp) = &srcset.webp {
+                                let source = format!(
+                                    r#"<source type="image/webp" srcset="{}" sizes="{}" />"#,
+                                    escape_attr(webp),
+                                    escape_attr(&sizes)
+                                );
+                                el.before(&format!("<picture>{}", source), ContentType::Html);
+                                el.after("</picture>", ContentType::Html);
+                            }
+                            for variant in srcset.variants {
+                                references
+                                    .borrow_mut()
+                                    .assets
+                                    .push((variant, asset.path.clone()));
+                            }
+                        }
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .map_err(handler_error)?;
+    Ok((content, references.into_inner()))
+}
+
+/// The file name of the page rendered from the markdown file at `path`: the markdown file's name
+/// without its date prefix, or the slug set in its front matter.
+fn page_file_name(path: &Path, slug: Option<&str>) -> Result<String, Error> {
+    // an explicit slug takes precedence over the one derived from the file name; it must not
+    // point outside of the out dir
+    if let Some(slug) = slug {
+        if slug.trim().is_empty() || slug.contains(['/', '\\']) || slug.contains("..") {
+            return Err(Error::InvalidSlug(path.to_path_buf(), slug.to_string()));
+        }
+        return Ok(format!("{}.html", slug));
+    }
+
+    let file_name = path.with_extension("html");
+    let mut file_name = file_name.file_name().unwrap().to_string_lossy().to_string();
+    if file_name_date(path).is_some() {
+        // remove date from filename; remove one more character that separates the date from
+        // the slug (don't care about whether it is a _, -, space, or something else)
+        file_name.replace_range(..11, "");
+    }
+    Ok(file_name)
+}
+
+/// The date a markdown file's name starts with (e.g. `2022-01-24-hello.md`).
+fn file_name_date(path: &Path) -> Option<NaiveDate> {
+    let file_name = path.file_name()?.to_str()?;
+    file_name
+        .get(..10)
+        .and_then(|prefix| NaiveDate::parse_from_str(prefix, "%F").ok())
+}
+
+/// Maps the canonical paths of the given markdown files to the file names of their pages. Files
+/// that cannot be read or have invalid front matter are skipped, as rendering them fails anyway.
+fn page_file_names(paths: &[PathBuf]) -> HashMap<PathBuf, String> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let input = fs::read_to_string(path).ok()?;
+            let (front_matter, _) = front_matter::parse(path, &input).ok()?;
+            let file_name = page_file_name(path, front_matter.slug.as_deref()).ok()?;
+            Some((fs::canonicalize(path).ok()?, file_name))
+        })
+        .collect()
+}
+
+/// Fails if a published post links to a post whose page is not written in this build (e.g. a
+/// draft).
+fn check_links(posts: &[Post]) -> Result<(), Error> {
+    let published = posts
+        .iter()
+        .filter(|post| post.is_published())
+        .collect::<Vec<_>>();
+    let pages = published
+        .iter()
+        .filter_map(|post| fs::canonicalize(&post.source).ok())
+        .collect::<HashSet<_>>();
+    for post in published {
+        if let Some(target) = post.links.iter().find(|target| !pages.contains(*target)) {
+            return Err(Error::UnknownPage(post.source.clone(), target.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Sorts the posts (newest first) and writes all pages that list them.
+fn create_listings(layout: &str, posts: &mut [Post]) -> Result<(), Error> {
+    posts.sort_by_key(|post| Reverse(post.created_at));
+    let posts = posts
+        .iter()
+        .filter(|post| post.is_published())
+        .collect::<Vec<_>>();
+    assets::write_manifest(&posts)?;
+    create_index(layout, &posts)?;
+    taxonomy::create(layout, &posts)?;
+    feed::create(&posts)?;
+
+    Ok(())
+}
+
+/// Prepares a layout by writing its stylesheets to the out dir and filling in the site's details.
+fn prepare_layout(layout: &str) -> Result<String, Error> {
+    let highlight_css = match code::theme_css()? {
+        Some(css) => hash_and_write("highlight", Some("css"), css)?,
+        None => {
+            fs::write(
+                Path::new(CONFIG.out_dir()).join(env!("HIGHLIGHT_CSS")),
+                include_str!(concat!(env!("OUT_DIR"), "/", env!("HIGHLIGHT_CSS"))),
+            )?;
+            env!("HIGHLIGHT_CSS").to_string()
+        }
+    };
+
     let element_content_handlers = vec![
         element!("link[rel=stylesheet]", |el| {
             if let Some(href) = el.get_attribute("href") {
@@ -32,14 +514,17 @@ fn main() -> Result<(), Error> {
                             Path::new(CONFIG.out_dir()).join(env!("NORMALIZE_CSS")),
                             include_str!(concat!(env!("OUT_DIR"), "/", env!("NORMALIZE_CSS"))),
                         )?;
-                        el.set_attribute("href", env!("NORMALIZE_CSS"))?;
+                        el.set_attribute("href", concat!("/", env!("NORMALIZE_CSS")))?;
                     }
                     "style.css" => {
                         fs::write(
                             Path::new(CONFIG.out_dir()).join(env!("STYLE_CSS")),
                             include_str!(concat!(env!("OUT_DIR"), "/", env!("STYLE_CSS"))),
                         )?;
-                        el.set_attribute("href", env!("STYLE_CSS"))?;
+                        el.set_attribute("href", concat!("/", env!("STYLE_CSS")))?;
+                    }
+                    "highlight.css" => {
+                        el.set_attribute("href", &format!("/{}", highlight_css))?;
                     }
                     _ => {}
                 }
@@ -51,6 +536,12 @@ fn main() -> Result<(), Error> {
             el.set_inner_content(CONFIG.site_name(), ContentType::Text);
             Ok(())
         }),
+        element!("body", |el| {
+            if CONFIG.serve() {
+                el.append(serve::RELOAD_SCRIPT, ContentType::Html);
+            }
+            Ok(())
+        }),
         element!("#header h1 a", |el| {
             el.set_inner_content(CONFIG.site_name(), ContentType::Html);
             Ok(())
@@ -72,186 +563,13 @@ fn main() -> Result<(), Error> {
             Ok(())
         }),
     ];
-    let layout = rewrite_str(
-        include_str!("theme/layout.html"),
+    Ok(rewrite_str(
+        layout,
         RewriteStrSettings {
             element_content_handlers,
             ..RewriteStrSettings::default()
         },
-    )?;
-
-    // write posts
-    let mut posts = Vec::new();
-    for path in CONFIG.files() {
-        let path = PathBuf::from(path);
-
-        if !path.is_file() {
-            return Err(Error::NonMarkdownFile(path));
-        }
-
-        let has_md_ext = path
-            .extension()
-            .map(|ext| ext.eq_ignore_ascii_case("md"))
-            .unwrap_or(false);
-        if !has_md_ext {
-            return Err(Error::NonMarkdownFile(path));
-        }
-
-        let input = fs::read_to_string(&path)?;
-
-        let mut events = Transformer::new(Parser::new_ext(&input, options));
-        let mut content = String::new();
-        html::push_html(&mut content, &mut events);
-
-        // Collect assets from post.
-        let content = rewrite_str(
-            &content,
-            RewriteStrSettings {
-                element_content_handlers: vec![
-                    // from image tags
-                    element!("img", |el| {
-                        if let Some(url) = el.get_attribute("src") {
-                            if url.is_empty() || Url::parse(&url).is_ok() {
-                                // Url::parse only succeeds for absolute URLs
-                                return Ok(());
-                            }
-
-                            let src = Path::new(&url);
-                            if src.is_absolute() {
-                                return Ok(());
-                            }
-
-                            // relative to post's markdown file
-                            let src = if let Some(base) = path.parent() {
-                                base.join(src)
-                            } else {
-                                src.to_path_buf()
-                            };
-                            if !src.is_file() {
-                                return Ok(());
-                            }
-
-                            let name = src.file_stem().and_then(|n| n.to_str()).unwrap_or("image");
-                            let ext = src.extension().and_then(|n| n.to_str());
-                            let new_src = hash_and_write(name, ext, &fs::read(&src)?)?;
-                            el.set_attribute("src", &new_src)?;
-
-                            // TODO: wrap in link
-                            // TODO: convert image type?
-                        }
-                        Ok(())
-                    }),
-                    // links
-                    element!("a", |el| {
-                        if let Some(url) = el.get_attribute("href") {
-                            if url.is_empty() || Url::parse(&url).is_ok() {
-                                // Url::parse only succeeds for absolute URLs
-                                return Ok(());
-                            }
-
-                            let src = Path::new(&url);
-                            if src.is_absolute() {
-                                return Ok(());
-                            }
-
-                            // relative to post's markdown file
-                            let src = if let Some(base) = path.parent() {
-                                base.join(src)
-                            } else {
-                                src.to_path_buf()
-                            };
-                            if !src.is_file() {
-                                return Ok(());
-                            }
-
-                            let name = src.file_stem().and_then(|n| n.to_str()).unwrap_or("image");
-                            let ext = src.extension().and_then(|n| n.to_str());
-                            let new_src = hash_and_write(name, ext, &fs::read(&src)?)?;
-                            el.set_attribute("href", &new_src)?;
-
-                            // TODO: wrap in link
-                            // TODO: convert image type?
-                            // TODO: reduce duplication with above code
-                        }
-                        Ok(())
-                    }),
-                ],
-                ..RewriteStrSettings::default()
-            },
-        )?;
-
-        let html = create_page(
-            &layout,
-            &content,
-            vec![element!("title", |el| {
-                if !events.meta().is_empty() {
-                    el.after(events.meta(), ContentType::Html);
-                }
-
-                if let Some(title) = events.title() {
-                    el.set_inner_content(
-                        &format!("{} - {}", title, CONFIG.site_name()),
-                        ContentType::Text,
-                    );
-                }
-
-                Ok(())
-            })],
-        )?;
-
-        // look for `<meta name="date" content="" />` to extract the posts creation date
-        let mut created_at = None;
-        if !events.meta().is_empty() {
-            rewrite_str(
-                events.meta(),
-                RewriteStrSettings {
-                    element_content_handlers: vec![element!("meta[name=date]", |el| {
-                        created_at = el.get_attribute("content").and_then(|content| {
-                            DateTime::parse_from_rfc3339(&content)
-                                .or_else(|_| DateTime::parse_from_rfc2822(&content))
-                                .map(|dt| DateTime::<Utc>::from_utc(dt.naive_utc(), Utc))
-                                .ok()
-                        });
-                        Ok(())
-                    })],
-                    ..RewriteStrSettings::default()
-                },
-            )?;
-        }
-
-        let mut out_path = PathBuf::new();
-        out_path.push(out_dir);
-        let file_name = path.with_extension("html");
-        let mut file_name = file_name.file_name().unwrap().to_string_lossy().to_string();
-
-        if let Ok(date) = NaiveDate::parse_from_str(&file_name[..10], "%F") {
-            // remove date from filename; remove one more character that separates the date from
-            // the slug (don't care about whether it is a _, -, space, or something else)
-            file_name.replace_range(..11, "");
-
-            if created_at.is_none() {
-                created_at = Some(DateTime::<Utc>::from_utc(date.and_hms(0, 0, 0), Utc));
-            }
-        }
-
-        let created_at = created_at.ok_or_else(|| Error::MissingDate(path.clone()))?;
-
-        out_path.push(&*file_name);
-        fs::write(out_path, html)?;
-
-        posts.push(Post {
-            file_name,
-            title: events.title().map(String::from).unwrap_or_default(),
-            content,
-            created_at,
-        });
-    }
-
-    posts.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-    create_index(&layout, &posts)?;
-    feed::create(&posts)?;
-
-    Ok(())
+    )?)
 }
 
 fn create_page(
@@ -274,7 +592,103 @@ fn create_page(
     )?)
 }
 
-fn create_index(layout: &str, posts: &[Post]) -> Result<(), Error> {
+fn create_index(layout: &str, posts: &[&Post]) -> Result<(), Error> {
+    let per_page = CONFIG.per_page().unwrap_or(posts.len()).max(1);
+    let pages = posts.chunks(per_page).collect::<Vec<_>>();
+    let page_count = pages.len().max(1);
+
+    for page in 1..=page_count {
+        let mut html = render_post_list(
+            pages
+                .get(page - 1)
+                .copied()
+                .unwrap_or_default()
+                .iter()
+                .copied(),
+        )?;
+
+        let prev = (page > 1).then(|| page_url(page - 1));
+        let next = (page < page_count).then(|| page_url(page + 1));
+        if prev.is_some() || next.is_some() {
+            html += r#"<nav class="pagination">"#;
+            if let Some(prev) = &prev {
+                html += &format!(r#"<a href="{}" rel="prev">Newer posts</a>"#, prev);
+            }
+            html += &format!(
+                r#"<span class="page">Page {} of {}</span>"#,
+                page, page_count
+            );
+            if let Some(next) = &next {
+                html += &format!(r#"<a href="{}" rel="next">Older posts</a>"#, next);
+            }
+            html += "</nav>";
+        }
+
+        let html = create_page(
+            layout,
+            &html,
+            vec![
+                element!("head", |el| {
+                    if feed::is_enabled() {
+                        el.append(
+                            "<link href=\"/feed.xml\" type=\"application/atom+xml\" \
+                                rel=\"alternate\" title=\"Atom feed\" />\n",
+                            ContentType::Html,
+                        );
+                    }
+                    if let Some(prev) = &prev {
+                        el.append(
+                            &format!("<link rel=\"prev\" href=\"{}\" />\n", prev),
+                            ContentType::Html,
+                        );
+                    }
+                    if let Some(next) = &next {
+                        el.append(
+                            &format!("<link rel=\"next\" href=\"{}\" />\n", next),
+                            ContentType::Html,
+                        );
+                    }
+
+                    Ok(())
+                }),
+                element!("title", |el| {
+                    if page > 1 {
+                        el.set_inner_content(
+                            &format!("Page {} - {}", page, CONFIG.site_name()),
+                            ContentType::Text,
+                        );
+                    }
+                    Ok(())
+                }),
+            ],
+        )?;
+
+        let mut out_path = PathBuf::new();
+        out_path.push(CONFIG.out_dir());
+        if page == 1 {
+            out_path.push("index.html");
+        } else {
+            out_path.push("page");
+            fs::create_dir_all(&out_path)?;
+            out_path.push(format!("{}.html", page));
+        }
+        fs::write(out_path, html)?;
+    }
+
+    Ok(())
+}
+
+/// The URL of the given (1-based) index page.
+fn page_url(page: usize) -> String {
+    if page == 1 {
+        "/".to_string()
+    } else {
+        format!("/page/{}.html", page)
+    }
+}
+
+/// Renders a `ul.posts` list using `theme/post.html` for each entry.
+fn render_post_list<'a>(posts: impl IntoIterator<Item = &'a Post>) -> Result<String, Error> {
     let mut html = r#"<ul class="posts">"#.to_string();
     for post in posts {
         html += "<li>";
@@ -283,8 +697,11 @@ fn create_index(layout: &str, posts: &[Post]) -> Result<(), Error> {
             RewriteStrSettings {
                 element_content_handlers: vec![
                     element!("a.post-link", |el| {
-                        let _ = el.set_attribute("href", &post.file_name);
+                        let _ = el.set_attribute("href", &format!("/{}", post.file_name));
                         el.set_inner_content(&post.title, ContentType::Text);
+                        if post.draft {
+                            el.after(r#" <span class="draft">Draft</span>"#, ContentType::Html);
+                        }
                         Ok(())
                     }),
                     element!("time", |el| {
@@ -303,48 +720,30 @@ fn create_index(layout: &str, posts: &[Post]) -> Result<(), Error> {
     }
     html += "</ul>";
 
-    let html = create_page(
-        layout,
-        &html,
-        vec![element!("head", |el| {
-            if CONFIG.url().is_some() {
-                el.append(
-                    "<link href=\"/feed.xml\" type=\"application/atom+xml\" \
-                        rel=\"alternate\" title=\"Atom feed\" />\n",
-                    ContentType::Html,
-                );
-            }
-
-            Ok(())
-        })],
-    )?;
+    Ok(html)
+}
 
-    let mut out_path = PathBuf::new();
-    out_path.push(CONFIG.out_dir());
-    out_path.push("index.html");
-    fs::write(out_path, html)?;
+/// The result of a handler of `lol_html`'s rewriter.
+type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
-    Ok(())
+/// Unwraps errors of this crate returned by rewriter handlers, to not hide them behind a generic
+/// rewriting error.
+fn handler_error(err: RewritingError) -> Error {
+    match err {
+        RewritingError::ContentHandlerError(err) => match err.downcast::<Error>() {
+            Ok(err) => *err,
+            Err(err) => Error::Html(RewritingError::ContentHandlerError(err)),
+        },
+        err => Error::Html(err),
+    }
 }
 
 fn hash_and_write(name: &str, ext: Option<&str>, content: impl AsRef<[u8]>) -> io::Result<String> {
-    let mut hasher = Sha256::new();
-    hasher.update(content.as_ref());
-    let hash = hasher.finalize();
-
+    let hash = content_hash(content.as_ref());
     let hashed_name = if let Some(ext) = ext {
-        format!(
-            "{}-{}.{}",
-            name,
-            base64::encode_config(&hash[..16], base64::URL_SAFE_NO_PAD),
-            ext
-        )
+        format!("{}-{}.{}", name, hash, ext)
     } else {
-        format!(
-            "{}-{}",
-            name,
-            base64::encode_config(&hash[..16], base64::URL_SAFE_NO_PAD)
-        )
+        format!("{}-{}", name, hash)
     };
     let mut path = PathBuf::new();
     path.push(CONFIG.out_dir());
@@ -353,12 +752,49 @@ fn hash_and_write(name: &str, ext: Option<&str>, content: impl AsRef<[u8]>) -> i
     Ok(hashed_name)
 }
 
+/// A URL-safe hash of the content, used to name files in the out dir.
+fn content_hash(content: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content);
+    base64::encode_config(&hasher.finalize()[..16], base64::URL_SAFE_NO_PAD)
+}
+
+fn escape_attr(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 #[derive(Debug)]
 pub struct Post {
+    source: PathBuf,
+    dependencies: Vec<PathBuf>,
+    /// The file names of the assets written to the out dir for the post, with their source files.
+    assets: Vec<(String, PathBuf)>,
+    /// The canonical paths of the markdown files the post links to.
+    links: Vec<PathBuf>,
     file_name: String,
     title: String,
     content: String,
     created_at: DateTime<Utc>,
+    updated_at: Option<DateTime<Utc>>,
+    description: Option<String>,
+    categories: Vec<String>,
+    tags: Vec<String>,
+    draft: bool,
+}
+
+impl Post {
+    /// Whether the post is part of the build; drafts and posts dated in the future are only
+    /// included if requested via `--drafts` and `--future`.
+    pub fn is_published(&self) -> bool {
+        is_published(self.draft, self.created_at)
+    }
+
+    pub fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at.unwrap_or(self.created_at)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -371,6 +807,44 @@ pub enum Error {
     NonMarkdownFile(PathBuf),
     #[error("could not extract date for post: {0}")]
     MissingDate(PathBuf),
+    #[error("invalid slug {1:?} in {0}: it must not be empty or contain `/`, `\\` or `..`")]
+    InvalidSlug(PathBuf, String),
     #[error("failed to write feed.xml")]
     Xml(#[from] xml::writer::Error),
+    #[error("invalid YAML front matter in {0}")]
+    Yaml(PathBuf, #[source] serde_yaml::Error),
+    #[error("invalid TOML front matter in {0}")]
+    Toml(PathBuf, #[source] toml::de::Error),
+    #[error("layout does not exist: {0}")]
+    MissingLayout(PathBuf),
+    #[error("no markdown files or content directories given")]
+    MissingInput,
+    #[error("failed to discover markdown files")]
+    Discover(#[from] ignore::Error),
+    #[error("failed to start server")]
+    Serve(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("failed to load syntax definitions from {0}")]
+    Syntax(PathBuf, #[source] syntect::LoadingError),
+    #[error("failed to read or write syntax dump {0}")]
+    SyntaxDump(PathBuf, #[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("no syntaxes directory found")]
+    MissingSyntaxes,
+    #[error("failed to load themes from {0}")]
+    Theme(PathBuf, #[source] syntect::LoadingError),
+    #[error("unknown highlighting theme {0} (available: {1})")]
+    UnknownTheme(String, String),
+    #[error("failed to include {0}")]
+    Include(PathBuf, #[source] io::Error),
+    #[error("region {1} not found in {0}")]
+    MissingRegion(PathBuf, String),
+    #[error("lines {1}-{2} do not exist in {0}")]
+    InvalidLines(PathBuf, usize, usize),
+    #[error("failed to process image {0}")]
+    Image(PathBuf, #[source] image::ImageError),
+    #[error("failed to write the asset manifest")]
+    Manifest(#[source] serde_json::Error),
+    #[error("{0} links to {1}, which is not part of the site")]
+    UnknownPage(PathBuf, PathBuf),
+    #[error("the terms {0} and {1} would share the page {2}")]
+    TermCollision(String, String, String),
 }
@@ -0,0 +1,1195 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use super::escape_attr;
+use pulldown_cmark::{CowStr, Event, Tag};
+
+/// Replaces `$...$` (inline) and `$$...$$` (display) math with MathML. Works on the parser's
+/// events together with their source ranges, as the math has to be taken from the markdown source
+/// before backslash escapes and emphasis are applied to it.
+pub struct Math<'a, I> {
+    source: &'a str,
+    events: I,
+    enabled: bool,
+    /// Events that were read ahead and still have to be processed.
+    lookahead: VecDeque<(Event<'a>, Range<usize>)>,
+    /// The end of the current block; math never spans multiple blocks.
+    block_end: usize,
+    in_code_block: bool,
+}
+
+impl<'a, I> Math<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    pub fn new(source: &'a str, events: I, enabled: bool) -> Self {
+        Self {
+            source,
+            events,
+            enabled,
+            lookahead: VecDeque::new(),
+            block_end: 0,
+            in_code_block: false,
+        }
+    }
+
+    fn next_event(&mut self) -> Option<(Event<'a>, Range<usize>)> {
+        self.lookahead.pop_front().or_else(|| self.events.next())
+    }
+
+    /// Renders a paragraph that only consists of display math without the paragraph around it.
+    fn display_paragraph(&mut self, range: &Range<usize>) -> Option<Event<'a>> {
+        let raw = self.source[range.clone()].trim();
+        let latex = raw.strip_prefix("$$")?.strip_suffix("$$")?;
+        if latex.contains("$$") || latex.trim().is_empty() {
+            return None;
+        }
+
+        // skip the paragraph's content
+        while let Some((event, _)) = self.next_event() {
+            if matches!(event, Event::End(Tag::Paragraph)) {
+                break;
+            }
+        }
+        Some(html(to_mathml(&decode_entities(latex), true) + "\n"))
+    }
+
+    /// Looks for math starting in the text with the given content and source range. Returns the
+    /// text before the math and queues the math and the rest of the text. Returns `None` if there
+    /// is no math.
+    fn inline(&mut self, content: &str, range: &Range<usize>) -> Option<Event<'a>> {
+        let source = self.source;
+        // text that differs from its source (escapes, entities, smart punctuation) is never math
+        if content != &source[range.clone()] {
+            return None;
+        }
+        let (open, close, display) = (range.start..range.end)
+            .filter(|&i| source.as_bytes()[i] == b'$' && !is_escaped(source, i))
+            .find_map(|i| {
+                let display = source[i..].starts_with("$$");
+                let content_start = i + if display { 2 } else { 1 };
+                let opens = display
+                    || source[content_start..]
+                        .chars()
+                        .next()
+                        .is_some_and(|c| !c.is_whitespace());
+                if !opens {
+                    return None;
+                }
+                let close = find_closing(source, content_start, self.block_end, display)?;
+                Some((i, close, display))
+            })?;
+        let delimiter = if display { 2 } else { 1 };
+        let end = close + delimiter;
+
+        // the math may only contain text (emphasis is caused by `*` or `_` in the math)
+        let mut consumed = 0;
+        while self
+            .lookahead
+            .back()
+            .is_none_or(|(_, range)| range.end < end)
+        {
+            match self.events.next() {
+                Some(event) => self.lookahead.push_back(event),
+                None => break,
+            }
+        }
+        let mut rest = None;
+        if range.end > end {
+            rest = Some((content[end - range.start..].to_string(), end..range.end));
+        }
+        for (event, range) in &self.lookahead {
+            if range.start >= end {
+                break;
+            }
+            match event {
+                Event::Text(t) if range.end > end => {
+                    if **t != source[range.clone()] {
+                        return None;
+                    }
+                    rest = Some((t[end - range.start..].to_string(), end..range.end));
+                }
+                Event::Text(_) | Event::SoftBreak => {}
+                Event::Start(Tag::Emphasis | Tag::Strong)
+                | Event::End(Tag::Emphasis | Tag::Strong)
+                    if range.start > open && range.end <= end => {}
+                // the end of the paragraph, item, emphasis, ... the math is in
+                Event::End(_) if range.start <= open && range.end >= end => break,
+                _ => return None,
+            }
+            consumed += 1;
+        }
+
+        self.lookahead.drain(..consumed);
+        if let Some((rest, range)) = rest {
+            self.lookahead.push_front((text(rest), range));
+        }
+        let latex = decode_entities(&source[open + delimiter..close]);
+        self.lookahead
+            .push_front((html(to_mathml(&latex, display)), open..end));
+
+        Some(text(content[..open - range.start].to_string()))
+    }
+}
+
+impl<'a, I> Iterator for Math<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (event, range) = self.next_event()?;
+        if !self.enabled {
+            return Some(event);
+        }
+
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => self.in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => self.in_code_block = false,
+            Event::Start(Tag::Paragraph) => {
+                self.block_end = range.end;
+                if let Some(math) = self.display_paragraph(&range) {
+                    return Some(math);
+                }
+            }
+            Event::Start(Tag::Heading(_) | Tag::TableCell | Tag::Item) => {
+                self.block_end = range.end
+            }
+            Event::Text(content) if !self.in_code_block && range.end <= self.block_end => {
+                if let Some(text) = self.inline(content, &range) {
+                    return Some(text);
+                }
+            }
+            _ => {}
+        }
+        Some(event)
+    }
+}
+
+fn html<'a>(html: String) -> Event<'a> {
+    Event::Html(CowStr::Boxed(html.into_boxed_str()))
+}
+
+fn text<'a>(text: String) -> Event<'a> {
+    Event::Text(CowStr::Boxed(text.into_boxed_str()))
+}
+
+/// Whether the character at the given index is escaped by an odd number of backslashes.
+fn is_escaped(source: &str, i: usize) -> bool {
+    source[..i]
+        .bytes()
+        .rev()
+        .take_while(|&b| b == b'\\')
+        .count()
+        % 2
+        == 1
+}
+
+/// Finds the closing delimiter of math whose content starts at `start`, which has to be the next
+/// unescaped `$` (or `$$` for display math). A closing `$` of inline math must not follow
+/// whitespace or be followed by a digit, so that e.g. `$5 and $10` is not treated as math.
+fn find_closing(source: &str, start: usize, end: usize, display: bool) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let i = (start..end.min(source.len())).find(|&i| bytes[i] == b'$' && !is_escaped(source, i))?;
+    if display {
+        return (source[i..].starts_with("$$") && i > start).then_some(i);
+    }
+    (i > start
+        && !bytes[i - 1].is_ascii_whitespace()
+        && !bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+    .then_some(i)
+}
+
+/// Decodes the HTML entities in math taken from the markdown source (which markdown decodes in
+/// text). Unknown entities are kept as they are.
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .map(|end| &rest[1..end + 1])
+            .filter(|name| {
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '#')
+            });
+        let c = entity.and_then(|name| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => {
+                let number = name.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (entity, c) {
+            (Some(name), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[name.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Converts LaTeX math into MathML. Supports the commonly used subset of LaTeX math: scripts,
+/// fractions, roots, greek letters and symbols, functions, accents, fonts, spacing, `\left`/`\right`
+/// and matrix-like environments. Unknown commands are rendered as errors.
+pub fn to_mathml(latex: &str, display: bool) -> String {
+    let mut parser = Parser {
+        tokens: tokenize(latex),
+        pos: 0,
+        display,
+    };
+    let mut content = Vec::new();
+    // the top level must not stop at stray closing braces or row breaks outside of environments
+    loop {
+        content.extend(parser.parse_row());
+        match parser.next() {
+            Some(Token::Close | Token::Ampersand | Token::NewRow) => continue,
+            Some(token) => content.push(error(&token.to_string())),
+            None => break,
+        }
+    }
+
+    format!(
+        r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="{}"><semantics><mrow>{}</mrow><annotation encoding="application/x-tex">{}</annotation></semantics></math>"#,
+        if display { "block" } else { "inline" },
+        content.concat(),
+        escape_attr(latex.trim())
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Command(String),
+    Number(String),
+    Char(char),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Ampersand,
+    /// `\\`
+    NewRow,
+    Prime,
+    /// The raw content of the group after a command like `\text`, in which whitespace matters.
+    Text(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Command(name) => write!(f, "\\{}", name),
+            Token::Number(n) => f.write_str(n),
+            Token::Char(c) => write!(f, "{}", c),
+            Token::Open => f.write_str("{"),
+            Token::Close => f.write_str("}"),
+            Token::Sup => f.write_str("^"),
+            Token::Sub => f.write_str("_"),
+            Token::Ampersand => f.write_str("&"),
+            Token::NewRow => f.write_str("\\\\"),
+            Token::Prime => f.write_str("'"),
+            Token::Text(text) => write!(f, "{{{}}}", text),
+        }
+    }
+}
+
+/// Commands whose argument is text rather than math.
+const TEXT_COMMANDS: &[&str] = &[
+    "text",
+    "textrm",
+    "textit",
+    "textbf",
+    "mbox",
+    "operatorname",
+    "mathbb",
+    "begin",
+    "end",
+];
+
+fn tokenize(latex: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = latex.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let mut name = c.to_string();
+                    while let Some(&c) = chars.peek() {
+                        if !c.is_ascii_alphabetic() {
+                            break;
+                        }
+                        name.push(c);
+                        chars.next();
+                    }
+                    let is_text = TEXT_COMMANDS.contains(&name.as_str());
+                    tokens.push(Token::Command(name));
+                    if is_text {
+                        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                            chars.next();
+                        }
+                        if chars.peek() == Some(&'{') {
+                            chars.next();
+                            let mut depth = 0;
+                            let mut text = String::new();
+                            for c in chars.by_ref() {
+                                match c {
+                                    '{' => depth += 1,
+                                    '}' if depth == 0 => break,
+                                    '}' => depth -= 1,
+                                    _ => {}
+                                }
+                                text.push(c);
+                            }
+                            tokens.push(Token::Text(text));
+                        }
+                    }
+                }
+                Some('\\') => tokens.push(Token::NewRow),
+                Some(c) => tokens.push(Token::Command(c.to_string())),
+                None => {}
+            },
+            c if c.is_ascii_digit()
+                || (c == '.' && chars.peek().is_some_and(char::is_ascii_digit)) =>
+            {
+                let mut number = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() && c != '.' {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Number(number));
+            }
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '^' => tokens.push(Token::Sup),
+            '_' => tokens.push(Token::Sub),
+            '&' => tokens.push(Token::Ampersand),
+            '\'' => tokens.push(Token::Prime),
+            '%' => {
+                // comment until the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    display: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Parses expressions until the end of the current group, row or cell.
+    fn parse_row(&mut self) -> Vec<String> {
+        let mut items = Vec::new();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Close | Token::Ampersand | Token::NewRow => break,
+                Token::Command(name) if name == "right" || name == "end" => break,
+                _ => {}
+            }
+            if let Some(item) = self.parse_scripted() {
+                items.push(item);
+            }
+        }
+        items
+    }
+
+    /// Parses an atom with its sub- and superscripts and primes.
+    fn parse_scripted(&mut self) -> Option<String> {
+        let limits =
+            matches!(self.peek(), Some(Token::Command(name)) if self.display && has_limits(name));
+        let base = match self.peek() {
+            Some(Token::Sup | Token::Sub | Token::Prime) => "<mrow></mrow>".to_string(),
+            _ => self.parse_atom()?,
+        };
+
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            match self.peek() {
+                Some(Token::Sub) if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.parse_argument());
+                }
+                Some(Token::Sup) if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.parse_argument());
+                }
+                Some(Token::Prime) if sup.is_none() => {
+                    let mut primes = String::new();
+                    while self.peek() == Some(&Token::Prime) {
+                        self.next();
+                        primes.push('′');
+                    }
+                    sup = Some(format!("<mo>{}</mo>", primes));
+                }
+                _ => break,
+            }
+        }
+
+        let (sub_tag, sup_tag, subsup_tag) = if limits {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        Some(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", sub_tag, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", sup_tag, base, sup),
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", subsup_tag, base, sub, sup),
+        })
+    }
+
+    /// Parses the argument of a command or script: a group or a single token.
+    fn parse_argument(&mut self) -> String {
+        // `x^23` only raises the 2
+        if let Some(Token::Number(number)) = self.peek() {
+            if number.chars().count() > 1 {
+                let mut rest = number.chars();
+                let first = rest.next().unwrap();
+                let rest = rest.as_str().to_string();
+                self.tokens[self.pos] = Token::Number(rest);
+                return format!("<mn>{}</mn>", first);
+            }
+        }
+        self.parse_atom()
+            .unwrap_or_else(|| "<mrow></mrow>".to_string())
+    }
+
+    /// Parses the text argument of a command like `\text`.
+    fn parse_text(&mut self) -> String {
+        match self.next() {
+            Some(Token::Text(text)) => text,
+            Some(token) => token.to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// Parses an optional `[...]` argument.
+    fn parse_optional(&mut self) -> Option<String> {
+        if self.peek() != Some(&Token::Char('[')) {
+            return None;
+        }
+        self.next();
+        let mut items = Vec::new();
+        while let Some(token) = self.peek() {
+            if token == &Token::Char(']') {
+                self.next();
+                break;
+            }
+            if matches!(token, Token::Close) {
+                break;
+            }
+            if let Some(item) = self.parse_scripted() {
+                items.push(item);
+            }
+        }
+        Some(mrow(items))
+    }
+
+    /// Parses a delimiter after `\left`, `\right` or `\big`.
+    fn parse_delimiter(&mut self) -> Option<String> {
+        match self.next()? {
+            Token::Char('.') => None,
+            Token::Char(c) => Some(c.to_string()),
+            Token::Command(name) => Some(
+                symbol(&name)
+                    .map(|(symbol, _)| symbol.to_string())
+                    .unwrap_or(name),
+            ),
+            token => Some(token.to_string()),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Option<String> {
+        let token = self.next()?;
+        Some(match token {
+            Token::Number(number) => format!("<mn>{}</mn>", number),
+            Token::Char(c) if c.is_alphabetic() => format!("<mi>{}</mi>", c),
+            Token::Char(c) => operator(&char_operator(c).to_string()),
+            Token::Open => {
+                let content = self.parse_row();
+                if self.peek() == Some(&Token::Close) {
+                    self.next();
+                }
+                mrow(content)
+            }
+            Token::Text(text) => format!("<mtext>{}</mtext>", escape_attr(&text)),
+            Token::Close | Token::Ampersand | Token::NewRow => return None,
+            Token::Sup | Token::Sub | Token::Prime => return None,
+            Token::Command(name) => self.parse_command(&name),
+        })
+    }
+
+    fn parse_command(&mut self, name: &str) -> String {
+        match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.parse_argument();
+                let denominator = self.parse_argument();
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let n = self.parse_argument();
+                let k = self.parse_argument();
+                format!(
+                    r#"<mrow><mo>(</mo><mfrac linethickness="0">{}{}</mfrac><mo>)</mo></mrow>"#,
+                    n, k
+                )
+            }
+            "sqrt" => match self.parse_optional() {
+                Some(index) => {
+                    let radicand = self.parse_argument();
+                    format!("<mroot>{}{}</mroot>", radicand, index)
+                }
+                None => format!("<msqrt>{}</msqrt>", self.parse_argument()),
+            },
+            "text" | "textrm" | "textit" | "textbf" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape_attr(&self.parse_text()))
+            }
+            "operatorname" => format!(
+                r#"<mi mathvariant="normal">{}</mi>"#,
+                escape_attr(&self.parse_text())
+            ),
+            "mathbb" => {
+                let text = self.parse_text();
+                let chars = text.chars().map(double_struck).collect::<String>();
+                format!("<mi>{}</mi>", chars)
+            }
+            "mathbf" | "mathit" | "mathrm" | "mathcal" | "mathfrak" | "mathsf" | "mathtt"
+            | "boldsymbol" | "bm" => {
+                let variant = match name {
+                    "mathbf" | "boldsymbol" | "bm" => "bold",
+                    "mathit" => "italic",
+                    "mathrm" => "normal",
+                    "mathcal" => "script",
+                    "mathfrak" => "fraktur",
+                    "mathsf" => "sans-serif",
+                    _ => "monospace",
+                };
+                let argument = self.parse_argument();
+                // MathML Core only supports mathvariant on token elements
+                argument.replace("<mi>", &format!(r#"<mi mathvariant="{}">"#, variant))
+            }
+            "hat" | "widehat" | "bar" | "overline" | "vec" | "dot" | "ddot" | "tilde"
+            | "widetilde" | "check" | "breve" | "acute" | "grave" | "overrightarrow" => {
+                let accent = match name {
+                    "hat" | "widehat" => "^",
+                    "bar" | "overline" => "¯",
+                    "vec" | "overrightarrow" => "→",
+                    "dot" => "˙",
+                    "ddot" => "¨",
+                    "tilde" | "widetilde" => "~",
+                    "check" => "ˇ",
+                    "breve" => "˘",
+                    "acute" => "´",
+                    _ => "`",
+                };
+                format!(
+                    r#"<mover accent="true">{}<mo>{}</mo></mover>"#,
+                    self.parse_argument(),
+                    accent
+                )
+            }
+            "underline" => format!(
+                r#"<munder accentunder="true">{}<mo>_</mo></munder>"#,
+                self.parse_argument()
+            ),
+            "overbrace" => format!(
+                r#"<mover accent="true">{}<mo>⏞</mo></mover>"#,
+                self.parse_argument()
+            ),
+            "underbrace" => format!(
+                r#"<munder accentunder="true">{}<mo>⏟</mo></munder>"#,
+                self.parse_argument()
+            ),
+            "left" => {
+                let open = self.parse_delimiter();
+                let content = self.parse_row();
+                let close = match self.peek() {
+                    Some(Token::Command(name)) if name == "right" => {
+                        self.next();
+                        self.parse_delimiter()
+                    }
+                    _ => None,
+                };
+                let fence = |delimiter: Option<String>| {
+                    delimiter
+                        .map(|d| format!(r#"<mo fence="true">{}</mo>"#, escape_attr(&d)))
+                        .unwrap_or_default()
+                };
+                format!(
+                    "<mrow>{}{}{}</mrow>",
+                    fence(open),
+                    content.concat(),
+                    fence(close)
+                )
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl"
+            | "biggr" | "Biggl" | "Biggr" => {
+                let size = match name.trim_end_matches(['l', 'r']) {
+                    "big" => "1.2em",
+                    "Big" => "1.6em",
+                    "bigg" => "2.05em",
+                    _ => "2.5em",
+                };
+                match self.parse_delimiter() {
+                    Some(delimiter) => format!(
+                        r#"<mo minsize="{0}" maxsize="{0}">{1}</mo>"#,
+                        size,
+                        escape_attr(&delimiter)
+                    ),
+                    None => String::new(),
+                }
+            }
+            "begin" => self.parse_environment(),
+            "," | "thinspace" => space("0.1667em"),
+            ":" | ">" | "medspace" => space("0.2222em"),
+            ";" | "thickspace" => space("0.2778em"),
+            "!" | "negthinspace" => space("-0.1667em"),
+            " " => space("0.25em"),
+            "quad" => space("1em"),
+            "qquad" => space("2em"),
+            "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+            _ => match symbol(name) {
+                Some((symbol, Kind::Identifier)) => format!("<mi>{}</mi>", escape_attr(symbol)),
+                Some((symbol, Kind::Operator)) => operator(symbol),
+                Some((symbol, Kind::LargeOperator)) => {
+                    format!(r#"<mo largeop="true" movablelimits="true">{}</mo>"#, symbol)
+                }
+                Some((symbol, Kind::Upright)) => {
+                    format!(r#"<mi mathvariant="normal">{}</mi>"#, symbol)
+                }
+                None => error(&format!("\\{}", name)),
+            },
+        }
+    }
+
+    /// Parses the content of `\begin{...}` up to the matching `\end{...}`.
+    fn parse_environment(&mut self) -> String {
+        let name = self.parse_text();
+        let (open, close, align) = match name.as_str() {
+            "matrix" | "smallmatrix" => ("", "", None),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("‖", "‖", None),
+            "cases" => ("{", "", Some("left left")),
+            "aligned" | "align" | "align*" | "split" | "gathered" => ("", "", Some("right left")),
+            "array" => {
+                // the column specification is not supported
+                self.parse_text();
+                ("", "", None)
+            }
+            _ => return error(&format!("\\begin{{{}}}", name)),
+        };
+
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            cells.push(format!("<mtd>{}</mtd>", self.parse_row().concat()));
+            match self.next() {
+                Some(Token::Ampersand) => {}
+                Some(Token::NewRow) => rows.push(std::mem::take(&mut cells)),
+                Some(Token::Command(end)) if end == "end" => {
+                    self.parse_text();
+                    break;
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        // ignore the empty row after a trailing `\\`
+        if cells.len() > 1 || cells.first().is_some_and(|cell| cell != "<mtd></mtd>") {
+            rows.push(cells);
+        }
+
+        let rows = rows
+            .into_iter()
+            .map(|cells| format!("<mtr>{}</mtr>", cells.concat()))
+            .collect::<String>();
+        let table = match align {
+            Some(align) => format!(r#"<mtable columnalign="{}">{}</mtable>"#, align, rows),
+            None => format!("<mtable>{}</mtable>", rows),
+        };
+        let fence = |delimiter: &str| {
+            if delimiter.is_empty() {
+                String::new()
+            } else {
+                format!(r#"<mo fence="true">{}</mo>"#, delimiter)
+            }
+        };
+        format!("<mrow>{}{}{}</mrow>", fence(open), table, fence(close))
+    }
+}
+
+enum Kind {
+    Identifier,
+    Operator,
+    /// Operators like `\sum`, whose scripts are placed below and above in display math.
+    LargeOperator,
+    /// Rendered upright, like functions (`\sin`) and capital greek letters.
+    Upright,
+}
+
+fn symbol(name: &str) -> Option<(&'static str, Kind)> {
+    use Kind::*;
+    Some(match name {
+        // greek letters
+        "alpha" => ("α", Identifier),
+        "beta" => ("β", Identifier),
+        "gamma" => ("γ", Identifier),
+        "delta" => ("δ", Identifier),
+        "epsilon" => ("ϵ", Identifier),
+        "varepsilon" => ("ε", Identifier),
+        "zeta" => ("ζ", Identifier),
+        "eta" => ("η", Identifier),
+        "theta" => ("θ", Identifier),
+        "vartheta" => ("ϑ", Identifier),
+        "iota" => ("ι", Identifier),
+        "kappa" => ("κ", Identifier),
+        "lambda" => ("λ", Identifier),
+        "mu" => ("μ", Identifier),
+        "nu" => ("ν", Identifier),
+        "xi" => ("ξ", Identifier),
+        "pi" => ("π", Identifier),
+        "varpi" => ("ϖ", Identifier),
+        "rho" => ("ρ", Identifier),
+        "varrho" => ("ϱ", Identifier),
+        "sigma" => ("σ", Identifier),
+        "varsigma" => ("ς", Identifier),
+        "tau" => ("τ", Identifier),
+        "upsilon" => ("υ", Identifier),
+        "phi" => ("ϕ", Identifier),
+        "varphi" => ("φ", Identifier),
+        "chi" => ("χ", Identifier),
+        "psi" => ("ψ", Identifier),
+        "omega" => ("ω", Identifier),
+        "Gamma" => ("Γ", Upright),
+        "Delta" => ("Δ", Upright),
+        "Theta" => ("Θ", Upright),
+        "Lambda" => ("Λ", Upright),
+        "Xi" => ("Ξ", Upright),
+        "Pi" => ("Π", Upright),
+        "Sigma" => ("Σ", Upright),
+        "Upsilon" => ("Υ", Upright),
+        "Phi" => ("Φ", Upright),
+        "Psi" => ("Ψ", Upright),
+        "Omega" => ("Ω", Upright),
+        // letter-like symbols
+        "infty" => ("∞", Identifier),
+        "partial" => ("∂", Identifier),
+        "nabla" => ("∇", Identifier),
+        "emptyset" | "varnothing" => ("∅", Identifier),
+        "ell" => ("ℓ", Identifier),
+        "hbar" => ("ℏ", Identifier),
+        "Re" => ("ℜ", Identifier),
+        "Im" => ("ℑ", Identifier),
+        "aleph" => ("ℵ", Identifier),
+        "angle" => ("∠", Identifier),
+        "triangle" => ("△", Identifier),
+        "top" => ("⊤", Identifier),
+        "bot" => ("⊥", Identifier),
+        // escaped characters
+        "{" | "lbrace" => ("{", Operator),
+        "}" | "rbrace" => ("}", Operator),
+        "%" => ("%", Operator),
+        "$" => ("$", Operator),
+        "#" => ("#", Operator),
+        "&" => ("&", Operator),
+        "_" => ("_", Operator),
+        "|" | "Vert" => ("‖", Operator),
+        "vert" => ("|", Operator),
+        // binary operators and relations
+        "pm" => ("±", Operator),
+        "mp" => ("∓", Operator),
+        "times" => ("×", Operator),
+        "div" => ("÷", Operator),
+        "cdot" => ("⋅", Operator),
+        "ast" => ("∗", Operator),
+        "star" => ("⋆", Operator),
+        "circ" => ("∘", Operator),
+        "bullet" => ("∙", Operator),
+        "oplus" => ("⊕", Operator),
+        "ominus" => ("⊖", Operator),
+        "otimes" => ("⊗", Operator),
+        "odot" => ("⊙", Operator),
+        "leq" | "le" => ("≤", Operator),
+        "geq" | "ge" => ("≥", Operator),
+        "neq" | "ne" => ("≠", Operator),
+        "approx" => ("≈", Operator),
+        "equiv" => ("≡", Operator),
+        "sim" => ("∼", Operator),
+        "simeq" => ("≃", Operator),
+        "cong" => ("≅", Operator),
+        "propto" => ("∝", Operator),
+        "ll" => ("≪", Operator),
+        "gg" => ("≫", Operator),
+        "prec" => ("≺", Operator),
+        "succ" => ("≻", Operator),
+        "in" => ("∈", Operator),
+        "notin" => ("∉", Operator),
+        "ni" => ("∋", Operator),
+        "subset" => ("⊂", Operator),
+        "supset" => ("⊃", Operator),
+        "subseteq" => ("⊆", Operator),
+        "supseteq" => ("⊇", Operator),
+        "cup" => ("∪", Operator),
+        "cap" => ("∩", Operator),
+        "setminus" => ("∖", Operator),
+        "wedge" | "land" => ("∧", Operator),
+        "vee" | "lor" => ("∨", Operator),
+        "neg" | "lnot" => ("¬", Operator),
+        "forall" => ("∀", Operator),
+        "exists" => ("∃", Operator),
+        "nexists" => ("∄", Operator),
+        "perp" => ("⊥", Operator),
+        "parallel" => ("∥", Operator),
+        "mid" => ("∣", Operator),
+        "vdash" => ("⊢", Operator),
+        "models" => ("⊨", Operator),
+        "colon" => (":", Operator),
+        // arrows
+        "to" | "rightarrow" => ("→", Operator),
+        "leftarrow" | "gets" => ("←", Operator),
+        "leftrightarrow" => ("↔", Operator),
+        "Rightarrow" => ("⇒", Operator),
+        "Leftarrow" => ("⇐", Operator),
+        "Leftrightarrow" | "iff" => ("⇔", Operator),
+        "implies" => ("⟹", Operator),
+        "impliedby" => ("⟸", Operator),
+        "mapsto" => ("↦", Operator),
+        "longrightarrow" => ("⟶", Operator),
+        "longleftarrow" => ("⟵", Operator),
+        "uparrow" => ("↑", Operator),
+        "downarrow" => ("↓", Operator),
+        // delimiters
+        "langle" => ("⟨", Operator),
+        "rangle" => ("⟩", Operator),
+        "lfloor" => ("⌊", Operator),
+        "rfloor" => ("⌋", Operator),
+        "lceil" => ("⌈", Operator),
+        "rceil" => ("⌉", Operator),
+        // dots
+        "ldots" | "dots" => ("…", Operator),
+        "cdots" => ("⋯", Operator),
+        "vdots" => ("⋮", Operator),
+        "ddots" => ("⋱", Operator),
+        "prime" => ("′", Operator),
+        // large operators
+        "sum" => ("∑", LargeOperator),
+        "prod" => ("∏", LargeOperator),
+        "coprod" => ("∐", LargeOperator),
+        "int" => ("∫", Operator),
+        "iint" => ("∬", Operator),
+        "iiint" => ("∭", Operator),
+        "oint" => ("∮", Operator),
+        "bigcup" => ("⋃", LargeOperator),
+        "bigcap" => ("⋂", LargeOperator),
+        "bigoplus" => ("⨁", LargeOperator),
+        "bigotimes" => ("⨂", LargeOperator),
+        "bigvee" => ("⋁", LargeOperator),
+        "bigwedge" => ("⋀", LargeOperator),
+        // functions
+        "sin" => ("sin", Upright),
+        "cos" => ("cos", Upright),
+        "tan" => ("tan", Upright),
+        "cot" => ("cot", Upright),
+        "sec" => ("sec", Upright),
+        "csc" => ("csc", Upright),
+        "arcsin" => ("arcsin", Upright),
+        "arccos" => ("arccos", Upright),
+        "arctan" => ("arctan", Upright),
+        "sinh" => ("sinh", Upright),
+        "cosh" => ("cosh", Upright),
+        "tanh" => ("tanh", Upright),
+        "log" => ("log", Upright),
+        "ln" => ("ln", Upright),
+        "lg" => ("lg", Upright),
+        "exp" => ("exp", Upright),
+        "det" => ("det", Upright),
+        "dim" => ("dim", Upright),
+        "ker" => ("ker", Upright),
+        "deg" => ("deg", Upright),
+        "gcd" => ("gcd", Upright),
+        "arg" => ("arg", Upright),
+        "hom" => ("hom", Upright),
+        "min" => ("min", Upright),
+        "max" => ("max", Upright),
+        "sup" => ("sup", Upright),
+        "inf" => ("inf", Upright),
+        "lim" => ("lim", Upright),
+        "liminf" => ("lim inf", Upright),
+        "limsup" => ("lim sup", Upright),
+        "Pr" => ("Pr", Upright),
+        "mod" | "bmod" => ("mod", Upright),
+        _ => return None,
+    })
+}
+
+/// Whether scripts of the command are placed below and above it in display math.
+fn has_limits(name: &str) -> bool {
+    matches!(
+        name,
+        "sum"
+            | "prod"
+            | "coprod"
+            | "bigcup"
+            | "bigcap"
+            | "bigoplus"
+            | "bigotimes"
+            | "bigvee"
+            | "bigwedge"
+            | "lim"
+            | "liminf"
+            | "limsup"
+            | "max"
+            | "min"
+            | "sup"
+            | "inf"
+            | "det"
+            | "gcd"
+            | "Pr"
+    )
+}
+
+/// Maps ASCII characters to their typographic counterparts in math.
+fn char_operator(c: char) -> char {
+    match c {
+        '-' => '−',
+        '*' => '∗',
+        '~' => '\u{a0}',
+        c => c,
+    }
+}
+
+fn double_struck(c: char) -> char {
+    match c {
+        'C' => 'ℂ',
+        'H' => 'ℍ',
+        'N' => 'ℕ',
+        'P' => 'ℙ',
+        'Q' => 'ℚ',
+        'R' => 'ℝ',
+        'Z' => 'ℤ',
+        c @ 'A'..='Z' => char::from_u32(0x1D538 + (c as u32 - 'A' as u32)).unwrap_or(c),
+        c @ 'a'..='z' => char::from_u32(0x1D552 + (c as u32 - 'a' as u32)).unwrap_or(c),
+        c @ '0'..='9' => char::from_u32(0x1D7D8 + (c as u32 - '0' as u32)).unwrap_or(c),
+        c => c,
+    }
+}
+
+fn operator(symbol: &str) -> String {
+    format!("<mo>{}</mo>", escape_attr(symbol))
+}
+
+fn space(width: &str) -> String {
+    format!(r#"<mspace width="{}"></mspace>"#, width)
+}
+
+fn error(source: &str) -> String {
+    format!("<merror><mtext>{}</mtext></merror>", escape_attr(source))
+}
+
+fn mrow(items: Vec<String>) -> String {
+    format!("<mrow>{}</mrow>", items.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Options, Parser};
+
+    fn render(markdown: &str) -> String {
+        let parser = Parser::new_ext(markdown, Options::all()).into_offset_iter();
+        let mut html = String::new();
+        html::push_html(&mut html, Math::new(markdown, parser, true));
+        html
+    }
+
+    fn inline(latex: &str) -> String {
+        to_mathml(latex, false)
+    }
+
+    #[test]
+    fn inline_math_in_paragraph() {
+        assert_eq!(
+            render("Hello $x$ world."),
+            format!("<p>Hello {} world.</p>\n", inline("x"))
+        );
+    }
+
+    #[test]
+    fn inline_math_at_end_of_document() {
+        assert_eq!(
+            render("Hello $x$"),
+            format!("<p>Hello {}</p>\n", inline("x"))
+        );
+    }
+
+    #[test]
+    fn inline_math_in_list_item() {
+        assert_eq!(
+            render("- item $y$"),
+            format!("<ul>\n<li>item {}</li>\n</ul>\n", inline("y"))
+        );
+    }
+
+    #[test]
+    fn inline_math_in_table_cell() {
+        assert!(render("| a |\n|---|\n| $z$ |\n").contains(&format!("<td>{}</td>", inline("z"))));
+    }
+
+    #[test]
+    fn inline_math_after_emphasis() {
+        assert_eq!(
+            render("A *b* $y$ c."),
+            format!("<p>A <em>b</em> {} c.</p>\n", inline("y"))
+        );
+    }
+
+    #[test]
+    fn inline_math_in_emphasis() {
+        assert_eq!(
+            render("*a $x$ b*"),
+            format!("<p><em>a {} b</em></p>\n", inline("x"))
+        );
+    }
+
+    #[test]
+    fn inline_math_with_emphasis_markers() {
+        assert_eq!(
+            render("Let $a_1 + b_1$ be."),
+            format!("<p>Let {} be.</p>\n", inline("a_1 + b_1"))
+        );
+    }
+
+    #[test]
+    fn multiple_inline_math() {
+        assert_eq!(
+            render("$a$ and $b$"),
+            format!("<p>{} and {}</p>\n", inline("a"), inline("b"))
+        );
+    }
+
+    #[test]
+    fn dollar_amounts_are_not_math() {
+        assert_eq!(render("$5 and $10"), "<p>$5 and $10</p>\n");
+    }
+
+    #[test]
+    fn math_does_not_swallow_dollar_amounts() {
+        assert_eq!(
+            render("Price is $5 and $10. Use $HOME and $PATH with $x^2$ here"),
+            format!(
+                "<p>Price is $5 and $10. Use $HOME and $PATH with {} here</p>\n",
+                inline("x^2")
+            )
+        );
+    }
+
+    #[test]
+    fn escaped_dollar_is_not_math() {
+        assert_eq!(render(r"\$x$ and \$y\$"), "<p>$x$ and $y$</p>\n");
+    }
+
+    #[test]
+    fn code_is_not_math() {
+        assert_eq!(render("`$x$`"), "<p><code>$x$</code></p>\n");
+        assert_eq!(render("```\n$x$\n```"), "<pre><code>$x$\n</code></pre>\n");
+    }
+
+    #[test]
+    fn text_around_math_keeps_entities() {
+        assert_eq!(
+            render("a &amp; $x$ &lt; b"),
+            format!("<p>a &amp; {} &lt; b</p>\n", inline("x"))
+        );
+    }
+
+    #[test]
+    fn text_around_math_keeps_smart_punctuation() {
+        assert_eq!(
+            render("It's $x$ -- \"quoted\""),
+            format!("<p>It’s {} – “quoted”</p>\n", inline("x"))
+        );
+    }
+
+    #[test]
+    fn entities_in_math_are_decoded() {
+        assert_eq!(
+            render("$a &lt; b$"),
+            format!("<p>{}</p>\n", inline("a < b"))
+        );
+    }
+
+    #[test]
+    fn display_math_paragraph() {
+        assert_eq!(render("$$\nx^2\n$$"), to_mathml("\nx^2\n", true) + "\n");
+    }
+
+    #[test]
+    fn disabled() {
+        let markdown = "Hello $x$ world.";
+        let parser = Parser::new_ext(markdown, Options::all()).into_offset_iter();
+        let mut html = String::new();
+        html::push_html(&mut html, Math::new(markdown, parser, false));
+        assert_eq!(html, "<p>Hello $x$ world.</p>\n");
+    }
+
+    #[test]
+    fn mathml() {
+        assert!(inline("x_i^2").contains("<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>"));
+        assert!(inline(r"\sqrt{x}").contains("<msqrt><mrow><mi>x</mi></mrow></msqrt>"));
+        assert!(inline(r"\text{if } x").contains("<mtext>if </mtext><mi>x</mi>"));
+        assert!(inline("f'(x)").contains("<msup><mi>f</mi><mo>′</mo></msup>"));
+        assert!(inline(r"\begin{pmatrix}a & b\\ c & d\end{pmatrix}").contains(
+            "<mtable><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr><mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable>"
+        ));
+        assert!(inline(r"\frac{a}{b}")
+            .contains("<mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi></mrow></mfrac>"));
+        assert!(inline("x^2").contains("<msup><mi>x</mi><mn>2</mn></msup>"));
+        assert!(inline(r"\alpha").contains("<mi>α</mi>"));
+        assert!(inline(r"\unknown").contains("<merror>"));
+        assert!(inline("a < b").contains("<mo>&lt;</mo>"));
+        assert!(inline("a < b")
+            .contains(r#"<annotation encoding="application/x-tex">a &lt; b</annotation>"#));
+    }
+}
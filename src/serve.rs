@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use super::Error;
+use crate::config::CONFIG;
+use percent_encoding::percent_decode_str;
+use tiny_http::{Header, Request, Response, Server};
+
+/// Incremented whenever a rebuild finishes; polled by [`RELOAD_SCRIPT`].
+static VERSION: AtomicUsize = AtomicUsize::new(0);
+
+const VERSION_PATH: &str = "/__mdr/version";
+
+/// Injected into every page while serving. Reloads the page once the site got rebuilt.
+pub const RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+  var version = null;
+  setInterval(function () {
+    fetch("/__mdr/version")
+      .then(function (res) { return res.text(); })
+      .then(function (v) {
+        if (version !== null && v !== version) location.reload();
+        version = v;
+      })
+      .catch(function () {});
+  }, 1000);
+})();
+</script>
+"#;
+
+/// Starts serving the out dir on localhost in a background thread.
+pub fn start() -> Result<(), Error> {
+    let server = Server::http(("127.0.0.1", CONFIG.port())).map_err(Error::Serve)?;
+    eprintln!("serving at http://localhost:{}/", CONFIG.port());
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            if let Err(err) = handle(request) {
+                eprintln!("error: {}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Notifies open pages that the site got rebuilt.
+pub fn reload() {
+    VERSION.fetch_add(1, Ordering::SeqCst);
+}
+
+fn handle(request: Request) -> Result<(), Error> {
+    let url = request.url();
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+
+    if path == VERSION_PATH {
+        let version = VERSION.load(Ordering::SeqCst).to_string();
+        return Ok(request.respond(Response::from_string(version))?);
+    }
+
+    let path = match resolve(&percent_decode_str(path).decode_utf8_lossy()) {
+        Some(path) => path,
+        None => {
+            return Ok(request.respond(Response::from_string("Not Found").with_status_code(404))?)
+        }
+    };
+
+    let content_type = content_type(&path);
+    let response = Response::from_file(File::open(path)?).with_header(
+        Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
+            .expect("valid content type header"),
+    );
+    Ok(request.respond(response)?)
+}
+
+/// Maps the request path to a file in the out dir; `/` and directories map to their `index.html`,
+/// and pretty URLs like `/some-post` to `/some-post.html`.
+fn resolve(path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let mut path = Path::new(CONFIG.out_dir()).join(relative);
+    if path.is_dir() {
+        path.push("index.html");
+    } else if !path.is_file() && path.extension().is_none() {
+        path.set_extension("html");
+    }
+
+    path.is_file().then_some(path)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match ext.as_str() {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "xml" => "application/atom+xml; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
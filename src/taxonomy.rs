@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use super::{create_page, escape_attr, render_post_list, Error, Post};
+use crate::config::CONFIG;
+use crate::transform::create_anchor;
+use lol_html::element;
+use lol_html::html_content::ContentType;
+
+/// The taxonomies posts can be grouped by. Each one results in an overview page at
+/// `/<dir>/index.html` and a page per term at `/<dir>/<slug>.html`.
+#[derive(Debug, Clone, Copy)]
+pub enum Taxonomy {
+    Categories,
+    Tags,
+}
+
+impl Taxonomy {
+    pub const ALL: [Taxonomy; 2] = [Taxonomy::Categories, Taxonomy::Tags];
+
+    fn dir(self) -> &'static str {
+        match self {
+            Taxonomy::Categories => "categories",
+            Taxonomy::Tags => "tags",
+        }
+    }
+
+    fn is_enabled(self) -> bool {
+        match self {
+            Taxonomy::Categories => CONFIG.taxonomies().categories,
+            Taxonomy::Tags => CONFIG.taxonomies().tags,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Taxonomy::Categories => "Categories",
+            Taxonomy::Tags => "Tags",
+        }
+    }
+
+    fn terms(self, post: &Post) -> &[String] {
+        match self {
+            Taxonomy::Categories => &post.categories,
+            Taxonomy::Tags => &post.tags,
+        }
+    }
+
+    fn term_url(self, term: &str) -> String {
+        format!("/{}/{}.html", self.dir(), create_anchor(term))
+    }
+}
+
+/// Renders the links to the term pages of the given categories and tags for a post page.
+pub fn render_links(categories: &[String], tags: &[String]) -> String {
+    let mut html = String::new();
+    for (taxonomy, terms) in [(Taxonomy::Categories, categories), (Taxonomy::Tags, tags)] {
+        // terms without a slug (e.g. only punctuation) don't get a page, see `create`
+        let terms = terms
+            .iter()
+            .filter(|term| !create_anchor(term).is_empty())
+            .collect::<Vec<_>>();
+        if terms.is_empty() || !taxonomy.is_enabled() {
+            continue;
+        }
+
+        html += &format!(
+            r#"<p class="taxonomy {}">{}: "#,
+            taxonomy.dir(),
+            taxonomy.title()
+        );
+        for (i, term) in terms.iter().enumerate() {
+            if i > 0 {
+                html += ", ";
+            }
+            html += &format!(
+                r#"<a href="{}" rel="tag">{}</a>"#,
+                escape_attr(&taxonomy.term_url(term)),
+                escape_attr(term)
+            );
+        }
+        html += "</p>";
+    }
+    html
+}
+
+/// Writes the overview and term pages of all taxonomies that are used by at least one post.
+pub fn create(layout: &str, posts: &[&Post]) -> Result<(), Error> {
+    for taxonomy in Taxonomy::ALL {
+        if !taxonomy.is_enabled() {
+            continue;
+        }
+
+        // group by slug so that e.g. `Rust` and `rust` end up on the same page; the first
+        // occurrence determines the displayed name
+        let mut terms: BTreeMap<String, (&str, Vec<&Post>)> = BTreeMap::new();
+        for &post in posts {
+            for term in taxonomy.terms(post) {
+                let slug = create_anchor(term);
+                if slug.is_empty() {
+                    continue;
+                }
+                let (name, posts) = terms
+                    .entry(slug)
+                    .or_insert_with(|| (term.as_str(), Vec::new()));
+                // different terms (e.g. `C++` and `C`) must not be merged into one page
+                if name.trim().to_lowercase() != term.trim().to_lowercase() {
+                    return Err(Error::TermCollision(
+                        name.to_string(),
+                        term.clone(),
+                        taxonomy.term_url(term),
+                    ));
+                }
+                posts.push(post);
+            }
+        }
+
+        if terms.is_empty() {
+            continue;
+        }
+
+        let mut out_dir = PathBuf::new();
+        out_dir.push(CONFIG.out_dir());
+        out_dir.push(taxonomy.dir());
+        fs::create_dir_all(&out_dir)?;
+
+        // overview
+        let mut html = format!(
+            r#"<h1>{}</h1><ul class="terms">"#,
+            escape_attr(taxonomy.title())
+        );
+        for (name, posts) in terms.values() {
+            html += &format!(
+                r#"<li><a href="{}">{}</a> <span class="count">{}</span></li>"#,
+                escape_attr(&taxonomy.term_url(name)),
+                escape_attr(name),
+                posts.len()
+            );
+        }
+        html += "</ul>";
+        let html = create_term_page(layout, &html, taxonomy.title())?;
+        fs::write(out_dir.join("index.html"), html)?;
+
+        // a page per term
+        for (slug, (name, posts)) in &terms {
+            let mut html = format!(
+                r#"<h1>{} <span class="term">{}</span></h1>"#,
+                escape_attr(taxonomy.title()),
+                escape_attr(name)
+            );
+            html += &render_post_list(posts.iter().copied())?;
+            let html = create_term_page(layout, &html, &format!("{}: {}", taxonomy.title(), name))?;
+            fs::write(out_dir.join(format!("{}.html", slug)), html)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn create_term_page(layout: &str, content: &str, title: &str) -> Result<String, Error> {
+    create_page(
+        layout,
+        content,
+        vec![element!("title", |el| {
+            el.set_inner_content(
+                &format!("{} - {}", title, CONFIG.site_name()),
+                ContentType::Text,
+            );
+            Ok(())
+        })],
+    )
+}
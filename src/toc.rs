@@ -0,0 +1,91 @@
+use super::{escape_attr, Error};
+use crate::config::CONFIG;
+use crate::transform::{Heading, TOC_PLACEHOLDER};
+use lol_html::html_content::ContentType;
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+
+/// Places the table of contents into the rendered post: at the `[[toc]]`/`<!-- toc -->` marker if
+/// there is one, otherwise right after the title for posts with enough headings. `enabled` (from
+/// the post's front matter) forces the table of contents on or off.
+pub fn insert(
+    content: String,
+    headings: &[Heading],
+    has_marker: bool,
+    enabled: Option<bool>,
+) -> Result<String, Error> {
+    // the post's title is not part of its table of contents
+    let headings = match headings.first() {
+        Some(first) if first.level == 1 => &headings[1..],
+        _ => headings,
+    };
+
+    let toc = render(headings);
+    if has_marker {
+        return Ok(rewrite_str(
+            &content,
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("nav.toc", |el| {
+                    if enabled == Some(false) || headings.is_empty() {
+                        el.remove();
+                    } else {
+                        el.set_inner_content(&toc, ContentType::Html);
+                    }
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )?);
+    }
+
+    let auto = CONFIG.toc().auto && headings.len() >= CONFIG.toc().min_headings;
+    if headings.is_empty() || !enabled.unwrap_or(auto) {
+        return Ok(content);
+    }
+
+    let nav = TOC_PLACEHOLDER.replace("></nav>", &format!(">{}</nav>", toc));
+    let mut inserted = false;
+    let content = rewrite_str(
+        &content,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("h1", |el| {
+                if !inserted {
+                    el.after(&nav, ContentType::Html);
+                    inserted = true;
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::default()
+        },
+    )?;
+
+    Ok(if inserted { content } else { nav + &content })
+}
+
+/// Renders the headings as nested lists according to their levels.
+fn render(headings: &[Heading]) -> String {
+    let mut html = String::new();
+    let mut levels: Vec<u32> = Vec::new();
+    for heading in headings {
+        while levels.last().map(|&l| l > heading.level).unwrap_or(false) {
+            html += "</li></ul>";
+            levels.pop();
+        }
+
+        if levels.last() == Some(&heading.level) {
+            html += "</li><li>";
+        } else {
+            html += "<ul><li>";
+            levels.push(heading.level);
+        }
+
+        html += &format!(
+            r##"<a href="#{}">{}</a>"##,
+            escape_attr(&heading.id),
+            escape_attr(&heading.text)
+        );
+    }
+    for _ in levels {
+        html += "</li></ul>";
+    }
+    html
+}
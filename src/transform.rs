@@ -1,60 +1,128 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use super::{escape_attr, Error};
+use crate::code::{self, CodeInfo};
 use deunicode::deunicode;
-use once_cell::sync::Lazy;
 use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag};
-use syntect::html::{ClassStyle, ClassedHTMLGenerator};
-use syntect::parsing::SyntaxSet;
-use syntect::parsing::{Scope, SyntaxDefinition};
-use syntect::util::LinesWithEndings;
-
-static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(|| {
-    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
-    builder.add(
-        SyntaxDefinition::load_from_str(
-            include_str!("../syntax/TypeScript.sublime-syntax"),
-            true,
-            None,
-        )
-        .unwrap(),
-    );
-    builder.add(
-        SyntaxDefinition::load_from_str(
-            include_str!("../syntax/TypeScriptReact.sublime-syntax"),
-            true,
-            None,
-        )
-        .unwrap(),
-    );
-    builder.build()
-});
 
-enum State<'a> {
+enum State {
     Heading {
         text: String,
+        /// Set via `{#custom-id}` at the end of the heading.
+        custom_id: Option<String>,
     },
     CodeBlock {
-        highlighter: ClassedHTMLGenerator<'a>,
+        info: CodeInfo,
+        code: String,
     },
 }
 
+/// Placeholder that is emitted for `[[toc]]` and `<!-- toc -->` markers; filled with the table of
+/// contents once all headings are known.
+pub const TOC_PLACEHOLDER: &str = "<nav class=\"toc\"></nav>\n";
+
+#[derive(Debug)]
+pub struct Heading {
+    pub level: u32,
+    pub text: String,
+    pub id: String,
+}
+
 pub struct Transformer<'a, I> {
     events: I,
+    /// Events that were read ahead and still have to be processed.
+    lookahead: VecDeque<Event<'a>>,
     next: Option<Event<'a>>,
-    state: Option<State<'a>>,
+    state: Option<State>,
     meta: String,
     title: Option<String>,
+    headings: Vec<Heading>,
+    /// The heading ids issued so far, used to avoid duplicates within the document.
+    ids: HashSet<String>,
+    has_toc_marker: bool,
+    /// Footnote labels in order of their first reference, with the number of references.
+    footnote_refs: Vec<(String, usize)>,
+    /// Footnote definitions, which are moved to the end of the document.
+    footnote_defs: Vec<(String, Vec<Event<'a>>)>,
+    footnotes_queued: bool,
+    /// Renders footnotes as sidenotes next to their first reference instead of at the end of the
+    /// document.
+    sidenotes: bool,
+    /// Whether all footnote definitions were already collected from the rest of the document.
+    footnote_defs_collected: bool,
+    /// The directory files included into code blocks are resolved relative to.
+    base_dir: PathBuf,
+    /// The files included into code blocks.
+    includes: Vec<PathBuf>,
+    /// The first error that occurred, as errors cannot be returned while iterating.
+    error: Option<Error>,
+    /// Whether each of the currently open blockquotes is rendered as a callout.
+    blockquotes: Vec<bool>,
+    /// Renders images with a title that are alone in a paragraph as figures.
+    figures: bool,
+    /// The number of paragraphs and headings the emitted events are currently in; a `<!-- toc -->`
+    /// marker is only replaced if it is a block of its own.
+    inline_depth: usize,
 }
 
 impl<'a, I> Transformer<'a, I> {
     pub fn new(events: I) -> Self {
         Self {
             events,
+            lookahead: VecDeque::new(),
             next: None,
             state: None,
             meta: String::new(),
             title: None,
+            headings: Vec::new(),
+            ids: HashSet::new(),
+            has_toc_marker: false,
+            footnote_refs: Vec::new(),
+            footnote_defs: Vec::new(),
+            footnotes_queued: false,
+            sidenotes: false,
+            footnote_defs_collected: false,
+            base_dir: PathBuf::new(),
+            includes: Vec::new(),
+            error: None,
+            blockquotes: Vec::new(),
+            figures: false,
+            inline_depth: 0,
         }
     }
 
+    /// Sets the directory files included into code blocks are resolved relative to (usually the
+    /// directory of the markdown file).
+    pub fn base_dir(mut self, dir: &Path) -> Self {
+        self.base_dir = dir.to_path_buf();
+        self
+    }
+
+    /// The files included into code blocks.
+    pub fn includes(&self) -> &[PathBuf] {
+        &self.includes
+    }
+
+    /// Returns the first error that occurred while transforming the document.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Renders footnotes as sidenotes (margin notes on wide screens, toggled inline on narrow
+    /// ones). Footnotes without a reference are still rendered at the end of the document.
+    pub fn sidenotes(mut self, enabled: bool) -> Self {
+        self.sidenotes = enabled;
+        self
+    }
+
+    /// Renders images with a title that are alone in a paragraph as a `<figure>` captioned with
+    /// the title, linking to the image. Individual images opt out via a trailing `{nofigure}`.
+    pub fn figures(mut self, enabled: bool) -> Self {
+        self.figures = enabled;
+        self
+    }
+
     pub fn meta(&self) -> &str {
         &self.meta
     }
@@ -62,44 +130,332 @@ impl<'a, I> Transformer<'a, I> {
     pub fn title(&self) -> Option<&str> {
         self.title.as_deref()
     }
+
+    /// All headings of the document in order of appearance.
+    pub fn headings(&self) -> &[Heading] {
+        &self.headings
+    }
+
+    /// Makes the id unique within the document by appending `-1`, `-2`, ... if necessary.
+    fn unique_id(&self, id: String) -> String {
+        let id = if id.is_empty() {
+            "section".to_string()
+        } else {
+            id
+        };
+        if !self.ids.contains(&id) {
+            return id;
+        }
+        (1..)
+            .map(|i| format!("{}-{}", id, i))
+            .find(|candidate| !self.ids.contains(candidate))
+            .unwrap()
+    }
+
+    /// Whether the document contains a `[[toc]]` or `<!-- toc -->` marker.
+    pub fn has_toc_marker(&self) -> bool {
+        self.has_toc_marker
+    }
 }
 
-impl<'a, I> Iterator for Transformer<'a, I>
+impl<'a, I> Transformer<'a, I>
 where
     I: Iterator<Item = Event<'a>>,
 {
-    type Item = Event<'a>;
+    /// Reads events into the lookahead until one matches `is_end`, and returns its index in the
+    /// lookahead (or the lookahead's length if the end of the document is reached before).
+    fn read_ahead(&mut self, is_end: impl Fn(&Event<'a>) -> bool) -> usize {
+        if let Some(i) = self.lookahead.iter().position(&is_end) {
+            return i;
+        }
+        for event in self.events.by_ref() {
+            let found = is_end(&event);
+            self.lookahead.push_back(event);
+            if found {
+                break;
+            }
+        }
+        self.lookahead
+            .iter()
+            .position(&is_end)
+            .unwrap_or(self.lookahead.len())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Removes the events of a footnote definition (up to its end) from the document to render
+    /// them in the footnotes section at the end of the document.
+    fn take_footnote_definition(&mut self, label: String) {
+        let end = self.read_ahead(|event| matches!(event, Event::End(Tag::FootnoteDefinition(_))));
+        let events = self.lookahead.drain(..end).collect::<Vec<_>>();
+        self.lookahead.pop_front(); // the end of the definition
+        self.add_footnote_definition(label, events);
+    }
+
+    fn add_footnote_definition(&mut self, label: String, mut events: Vec<Event<'a>>) {
+        if let Some((_, existing)) = self.footnote_defs.iter_mut().find(|(l, _)| *l == label) {
+            existing.append(&mut events);
+        } else {
+            self.footnote_defs.push((label, events));
+        }
+    }
+
+    /// Reads the rest of the document to collect all footnote definitions, as sidenotes are
+    /// rendered at their reference, which usually comes before the definition.
+    fn collect_footnote_definitions(&mut self) {
+        if self.footnote_defs_collected {
+            return;
+        }
+        self.footnote_defs_collected = true;
+
+        self.read_ahead(|_| false);
+        let mut rest = std::mem::take(&mut self.lookahead).into_iter();
+        while let Some(event) = rest.next() {
+            match event {
+                Event::Start(Tag::FootnoteDefinition(label)) => {
+                    let events = rest
+                        .by_ref()
+                        .take_while(|event| {
+                            !matches!(event, Event::End(Tag::FootnoteDefinition(_)))
+                        })
+                        .collect();
+                    self.add_footnote_definition(label.to_string(), events);
+                }
+                event => self.lookahead.push_back(event),
+            }
+        }
+    }
+
+    /// Turns a blockquote starting with a `[!NOTE]` (or `[!TIP]`, `[!IMPORTANT]`, `[!WARNING]`,
+    /// `[!CAUTION]`) marker into a callout, whose title is the rest of the marker's line (or the
+    /// name of its kind). Returns `false` if the blockquote is not a callout.
+    fn start_callout(&mut self) -> bool {
+        let end = self.read_ahead(|event| {
+            matches!(
+                event,
+                Event::SoftBreak | Event::End(Tag::Paragraph) | Event::End(Tag::BlockQuote)
+            )
+        });
+        if !matches!(self.lookahead.front(), Some(Event::Start(Tag::Paragraph))) {
+            return false;
+        }
+
+        // the marker is split into multiple text events
+        let mut text = String::new();
+        let mut count = 0;
+        for event in self.lookahead.range(1..end) {
+            match event {
+                Event::Text(t) => text.push_str(t),
+                _ => break,
+            }
+            count += 1;
+        }
+        let (kind, rest) = match split_callout_marker(&text) {
+            Some(marker) => marker,
+            None => return false,
+        };
+
+        self.lookahead.drain(1..=count);
+        let rest = rest.trim_start();
+        let title = if rest.is_empty() && end == count + 1 {
+            vec![Event::Text(CowStr::Borrowed(kind.title()))]
+        } else {
+            let mut title = self.lookahead.drain(1..end - count).collect::<Vec<_>>();
+            if !rest.is_empty() {
+                title.insert(0, Event::Text(CowStr::Boxed(rest.into())));
+            }
+            title
+        };
+        // drop the marker's line break, or its whole paragraph if the marker is all there is
+        if matches!(self.lookahead.get(1), Some(Event::SoftBreak)) {
+            self.lookahead.remove(1);
+        } else if matches!(self.lookahead.get(1), Some(Event::End(Tag::Paragraph))) {
+            self.lookahead.drain(..2);
+        }
+
+        self.lookahead.push_front(html("</p>\n".to_string()));
+        for event in title.into_iter().rev() {
+            self.lookahead.push_front(event);
+        }
+        self.lookahead.push_front(html(format!(
+            r#"<aside class="callout callout-{}"><p class="callout-title"><span class="callout-icon" aria-hidden="true">{}</span>"#,
+            kind.name(),
+            kind.icon()
+        )));
+
+        true
+    }
+
+    /// Replaces the paragraph (whose end is at `end` in the lookahead) with a figure if it only
+    /// consists of an image with a title. Strips a trailing `{nofigure}` from a paragraph that only
+    /// consists of an image. Returns `false` if the paragraph was left as it is.
+    fn take_figure(&mut self, end: usize) -> bool {
+        if !matches!(self.lookahead.front(), Some(Event::Start(Tag::Image(..)))) {
+            return false;
+        }
+        let mut depth = 0;
+        let image_end = self.lookahead.range(..end).position(|event| {
+            match event {
+                Event::Start(Tag::Image(..)) => depth += 1,
+                Event::End(Tag::Image(..)) => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        });
+        let image_end = match image_end {
+            Some(i) => i,
+            None => return false,
+        };
+
+        match end - image_end {
+            1 => {}
+            2 if matches!(&self.lookahead[image_end + 1], Event::Text(t) if t.trim() == "{nofigure}") =>
+            {
+                self.lookahead.remove(image_end + 1);
+                return false;
+            }
+            _ => return false,
+        }
+        if !self.figures {
+            return false;
+        }
+
+        let (link_type, url, title) = match &self.lookahead[0] {
+            Event::Start(Tag::Image(link_type, url, title)) if !title.trim().is_empty() => {
+                (*link_type, url.clone(), title.clone())
+            }
+            _ => return false,
+        };
+        // the title becomes the caption, so it is not repeated as the image's tooltip
+        self.lookahead[0] = Event::Start(Tag::Image(link_type, url.clone(), CowStr::Borrowed("")));
+        // the end of the paragraph
+        self.lookahead.remove(end);
+        self.lookahead
+            .insert(image_end + 1, html("</a><figcaption>".to_string()));
+        self.lookahead.insert(image_end + 2, Event::Text(title));
+        self.lookahead
+            .insert(image_end + 3, html("</figcaption></figure>\n".to_string()));
+        self.lookahead.push_front(html(format!(
+            r#"<figure class="image"><a href="{}">"#,
+            escape_attr(&url)
+        )));
+
+        true
+    }
+
+    /// Queues the footnotes section (in order of the first reference to each footnote, followed by
+    /// unreferenced ones) to be processed at the end of the document. Returns `false` if there is
+    /// nothing to queue.
+    fn queue_footnotes(&mut self) -> bool {
+        if self.footnotes_queued || self.footnote_defs.is_empty() {
+            return false;
+        }
+        self.footnotes_queued = true;
+
+        let mut defs = std::mem::take(&mut self.footnote_defs);
+        let mut footnotes = Vec::new();
+        for (i, (label, count)) in self.footnote_refs.iter().enumerate() {
+            if let Some(pos) = defs.iter().position(|(l, _)| l == label) {
+                footnotes.push((i + 1, *count, defs.remove(pos).1));
+            }
+        }
+        let offset = self.footnote_refs.len();
+        for (i, (_, events)) in defs.into_iter().enumerate() {
+            footnotes.push((offset + i + 1, 0, events));
+        }
+
+        self.lookahead.push_back(html(
+            r#"<section class="footnotes" role="doc-endnotes"><ol>"#.to_string(),
+        ));
+        for (n, count, mut events) in footnotes {
+            self.lookahead
+                .push_back(html(format!(r#"<li id="fn-{}" value="{}">"#, n, n)));
+
+            let backrefs = (1..=count)
+                .map(|i| {
+                    format!(
+                        r##" <a href="#{}" class="footnote-backref" role="doc-backlink" aria-label="Back to reference {}">↩{}</a>"##,
+                        footnote_ref_id(n, i),
+                        n,
+                        if i > 1 { format!("<sup>{}</sup>", i) } else { String::new() }
+                    )
+                })
+                .collect::<String>();
+            // place the back-references at the end of the last paragraph
+            if !backrefs.is_empty() {
+                let backrefs = html(backrefs);
+                match events.last() {
+                    Some(Event::End(Tag::Paragraph)) => events.insert(events.len() - 1, backrefs),
+                    _ => events.push(backrefs),
+                }
+            }
+
+            self.lookahead.extend(events);
+            self.lookahead.push_back(html("</li>".to_string()));
+        }
+        self.lookahead
+            .push_back(html("</ol></section>".to_string()));
+
+        true
+    }
+}
+
+impl<'a, I> Transformer<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    fn next_event(&mut self) -> Option<Event<'a>> {
         if let Some(next) = self.next.take() {
             return Some(next);
         }
 
-        for event in &mut self.events {
+        loop {
+            let event = match self.lookahead.pop_front().or_else(|| self.events.next()) {
+                Some(event) => event,
+                None if self.queue_footnotes() => continue,
+                None => return None,
+            };
             // dbg!(&event);
 
             match event {
                 Event::Start(Tag::Heading(_)) => {
+                    // read ahead to the end of the heading to strip a trailing `{#custom-id}`
+                    let end = self.read_ahead(|event| matches!(event, Event::End(Tag::Heading(_))));
+                    let mut custom_id = None;
+                    let last_text = end
+                        .checked_sub(1)
+                        .and_then(|i| self.lookahead.get_mut(i))
+                        .filter(|event| matches!(event, Event::Text(_)));
+                    if let Some(Event::Text(text)) = last_text {
+                        if let Some((rest, id)) = split_custom_id(text) {
+                            custom_id = Some(id.to_string());
+                            *text = CowStr::Boxed(rest.to_string().into_boxed_str());
+                        }
+                    }
+
                     self.state = Some(State::Heading {
                         text: String::new(),
+                        custom_id,
                     });
 
                     return Some(event);
                 }
 
-                Event::End(Tag::Heading(_)) => {
-                    if let Some(State::Heading { text }) = self.state.take() {
-                        let id = create_anchor(&text); // TODO: avoid duplicates
+                Event::End(Tag::Heading(level)) => {
+                    if let Some(State::Heading { text, custom_id }) = self.state.take() {
+                        // explicit ids are deduplicated as well, so that every anchor works
+                        let id = self.unique_id(custom_id.unwrap_or_else(|| create_anchor(&text)));
+                        self.ids.insert(id.clone());
+
                         let anchor = format!(
                             r##"<a href="#{}" id="{}" class="anchor" aria-hidden="true" tabindex="-1">{}</a>"##,
-                            id,
-                            id,
+                            escape_attr(&id),
+                            escape_attr(&id),
                             include_str!("theme/link.svg"),
                         );
 
                         if self.title.is_none() {
-                            self.title = Some(text);
+                            self.title = Some(text.clone());
                         }
+                        self.headings.push(Heading { level, text, id });
 
                         self.next = Some(event);
 
@@ -109,32 +465,32 @@ where
                     };
                 }
 
-                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang))) => {
-                    let syntax = Scope::new(&format!("source.{}", lang))
-                        .ok()
-                        .and_then(|scope| SYNTAX_SET.find_syntax_by_scope(scope))
-                        .or_else(|| SYNTAX_SET.find_syntax_by_name(lang))
-                        .or_else(|| SYNTAX_SET.find_syntax_by_extension(lang));
-
-                    if let Some(syntax) = syntax {
-                        self.state = Some(State::CodeBlock {
-                            highlighter: ClassedHTMLGenerator::new_with_class_style(
-                                syntax,
-                                &SYNTAX_SET,
-                                ClassStyle::Spaced,
-                            ),
-                        });
-                    }
-
-                    return Some(event);
+                // fenced code blocks are rendered as a whole once all of their code is known
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
+                    self.state = Some(State::CodeBlock {
+                        info: CodeInfo::parse(info),
+                        code: String::new(),
+                    });
+                    continue;
                 }
 
                 Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
-                    if let Some(State::CodeBlock { highlighter }) = self.state.take() {
-                        self.next = Some(event);
-                        return Some(Event::Html(CowStr::Boxed(
-                            highlighter.finalize().into_boxed_str(),
-                        )));
+                    if let Some(State::CodeBlock { info, mut code }) = self.state.take() {
+                        let mut line_numbers = Vec::new();
+                        if let Some(file) = &info.file {
+                            let path = self.base_dir.join(file);
+                            match code::include(&path, &info) {
+                                Ok((included, numbers)) => {
+                                    code = included;
+                                    line_numbers = numbers;
+                                }
+                                Err(err) => {
+                                    self.error.get_or_insert(err);
+                                }
+                            }
+                            self.includes.push(path);
+                        }
+                        return Some(html(code::render(&info, &code, &line_numbers)));
                     } else {
                         return Some(event);
                     }
@@ -142,14 +498,13 @@ where
 
                 Event::Text(ref text) => {
                     match &mut self.state {
-                        Some(State::Heading { text: heading_text }) => {
+                        Some(State::Heading {
+                            text: heading_text, ..
+                        }) => {
                             heading_text.push_str(text);
                         }
-                        Some(State::CodeBlock { highlighter }) => {
-                            for line in LinesWithEndings::from(text) {
-                                highlighter.parse_html_for_line_which_includes_newline(line);
-                            }
-
+                        Some(State::CodeBlock { code, .. }) => {
+                            code.push_str(text);
                             continue;
                         }
                         _ => {}
@@ -159,13 +514,123 @@ where
                 }
 
                 Event::Code(ref code) => {
-                    if let Some(State::Heading { text: heading_text }) = &mut self.state {
+                    if let Some(State::Heading {
+                        text: heading_text, ..
+                    }) = &mut self.state
+                    {
                         heading_text.push_str(code);
                     }
 
+                    // a `{:lang}` hint right after inline code highlights it
+                    self.read_ahead(|_| true);
+                    if let Some(Event::Text(text)) = self.lookahead.front_mut() {
+                        if let Some((lang, rest)) = split_lang_hint(text) {
+                            let html = code::render_inline(lang, code);
+                            if rest.is_empty() {
+                                self.lookahead.pop_front();
+                            } else {
+                                *text = CowStr::Boxed(rest.to_string().into_boxed_str());
+                            }
+                            return Some(Event::Html(CowStr::Boxed(html.into_boxed_str())));
+                        }
+                    }
+
+                    return Some(event);
+                }
+
+                // a paragraph only consisting of `[[toc]]` marks the position of the table of
+                // contents, and one only consisting of an image with a title becomes a figure; the
+                // paragraph's text is split into multiple events, so read ahead
+                Event::Start(Tag::Paragraph) if self.state.is_none() => {
+                    let end = self.read_ahead(|event| matches!(event, Event::End(Tag::Paragraph)));
+                    if self.take_figure(end) {
+                        continue;
+                    }
+                    // a `{nofigure}` might have been removed from the paragraph
+                    let end = self.read_ahead(|event| matches!(event, Event::End(Tag::Paragraph)));
+
+                    let mut text = String::new();
+                    for e in self.lookahead.range(..end) {
+                        match e {
+                            Event::Text(t) => text.push_str(t),
+                            _ => return Some(event),
+                        }
+                    }
+                    if text.trim() == "[[toc]]" {
+                        self.lookahead.drain(..=end);
+                        self.has_toc_marker = true;
+                        return Some(Event::Html(CowStr::Borrowed(TOC_PLACEHOLDER)));
+                    }
+
+                    return Some(event);
+                }
+
+                Event::Start(Tag::BlockQuote) => {
+                    let is_callout = self.start_callout();
+                    self.blockquotes.push(is_callout);
+                    if is_callout {
+                        continue;
+                    }
+                    return Some(event);
+                }
+
+                Event::End(Tag::BlockQuote) => {
+                    if self.blockquotes.pop() == Some(true) {
+                        return Some(html("</aside>\n".to_string()));
+                    }
                     return Some(event);
                 }
 
+                Event::Start(Tag::FootnoteDefinition(label)) => {
+                    self.take_footnote_definition(label.to_string());
+                    continue;
+                }
+
+                Event::FootnoteReference(label) => {
+                    let label = label.to_string();
+                    let (n, count) = match self.footnote_refs.iter().position(|(l, _)| *l == label)
+                    {
+                        Some(i) => {
+                            self.footnote_refs[i].1 += 1;
+                            (i + 1, self.footnote_refs[i].1)
+                        }
+                        None => {
+                            self.footnote_refs.push((label, 1));
+                            (self.footnote_refs.len(), 1)
+                        }
+                    };
+
+                    if self.sidenotes {
+                        self.collect_footnote_definitions();
+                        let label = &self.footnote_refs[n - 1].0;
+                        if let Some(pos) = self.footnote_defs.iter().position(|(l, _)| l == label) {
+                            let (_, events) = self.footnote_defs.remove(pos);
+                            return Some(html(render_sidenote(n, events)));
+                        }
+                        // subsequent references toggle the sidenote of the first one
+                        if count > 1 {
+                            return Some(html(format!(
+                                r#"<label for="sn-{}" class="sidenote-ref" id="{}" role="doc-noteref">{}</label>"#,
+                                n,
+                                footnote_ref_id(n, count),
+                                n
+                            )));
+                        }
+                    }
+
+                    return Some(html(format!(
+                        r##"<sup class="footnote-ref"><a href="#fn-{}" id="{}" role="doc-noteref">{}</a></sup>"##,
+                        n,
+                        footnote_ref_id(n, count),
+                        n
+                    )));
+                }
+
+                Event::Html(html) if html.trim() == "<!-- toc -->" && self.inline_depth == 0 => {
+                    self.has_toc_marker = true;
+                    return Some(Event::Html(CowStr::Borrowed(TOC_PLACEHOLDER)));
+                }
+
                 // extract <meta /> tags to move them into the head of the document
                 Event::Html(html) if html.starts_with("<meta ") => {
                     self.meta += &html;
@@ -175,15 +640,319 @@ where
                 _ => return Some(event),
             }
         }
+    }
+}
+
+impl<'a, I> Iterator for Transformer<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.next_event()?;
+        match event {
+            Event::Start(Tag::Paragraph | Tag::Heading(_)) => self.inline_depth += 1,
+            Event::End(Tag::Paragraph | Tag::Heading(_)) => {
+                self.inline_depth = self.inline_depth.saturating_sub(1)
+            }
+            _ => {}
+        }
+        Some(event)
+    }
+}
+
+fn html<'a>(html: String) -> Event<'a> {
+    Event::Html(CowStr::Boxed(html.into_boxed_str()))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Callout {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl Callout {
+    fn name(self) -> &'static str {
+        match self {
+            Callout::Note => "note",
+            Callout::Tip => "tip",
+            Callout::Important => "important",
+            Callout::Warning => "warning",
+            Callout::Caution => "caution",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Callout::Note => "Note",
+            Callout::Tip => "Tip",
+            Callout::Important => "Important",
+            Callout::Warning => "Warning",
+            Callout::Caution => "Caution",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Callout::Note => include_str!("theme/callout-note.svg"),
+            Callout::Tip => include_str!("theme/callout-tip.svg"),
+            Callout::Important => include_str!("theme/callout-important.svg"),
+            Callout::Warning => include_str!("theme/callout-warning.svg"),
+            Callout::Caution => include_str!("theme/callout-caution.svg"),
+        }
+    }
+}
+
+/// Splits a leading `[!KIND]` callout marker (case-insensitive) from the text following it.
+fn split_callout_marker(text: &str) -> Option<(Callout, &str)> {
+    let rest = text.strip_prefix("[!")?;
+    let end = rest.find(']')?;
+    let kind = match rest[..end].to_ascii_lowercase().as_str() {
+        "note" => Callout::Note,
+        "tip" => Callout::Tip,
+        "important" => Callout::Important,
+        "warning" => Callout::Warning,
+        "caution" => Callout::Caution,
+        _ => return None,
+    };
+    Some((kind, &rest[end + 1..]))
+}
+
+/// Renders the sidenote for the first reference to the footnote with the given number. The
+/// sidenote is placed inside of the paragraph of the reference, so the paragraphs of its
+/// definition are turned into line breaks.
+fn render_sidenote<'a>(number: usize, events: Vec<Event<'a>>) -> String {
+    let events = events.into_iter().filter_map(|event| match event {
+        Event::Start(Tag::Paragraph) => None,
+        Event::End(Tag::Paragraph) => Some(html("<br />".to_string())),
+        event => Some(event),
+    });
+    let mut content = String::new();
+    pulldown_cmark::html::push_html(&mut content, Transformer::new(events));
+    while let Some(rest) = content.trim_end().strip_suffix("<br />") {
+        content.truncate(rest.len());
+    }
+
+    format!(
+        r#"<label for="sn-{n}" class="sidenote-ref" id="fnref-{n}" role="doc-noteref">{n}</label><input type="checkbox" id="sn-{n}" class="sidenote-toggle" /><span class="sidenote" id="fn-{n}" role="doc-footnote"><span class="sidenote-number">{n}</span> {}</span>"#,
+        content.trim_end(),
+        n = number
+    )
+}
 
-        None
+/// The id of the `nth` reference to the footnote with the given number.
+fn footnote_ref_id(number: usize, nth: usize) -> String {
+    if nth > 1 {
+        format!("fnref-{}-{}", number, nth)
+    } else {
+        format!("fnref-{}", number)
     }
 }
 
-fn create_anchor(s: &str) -> String {
-    deunicode(s)
-        .to_lowercase()
-        .chars()
-        .map(|c| if c.is_alphanumeric() { c } else { '-' })
-        .collect()
+/// Creates a slug from the given text, e.g. `Hello, World!` becomes `hello-world`.
+pub fn create_anchor(s: &str) -> String {
+    let mut anchor = String::with_capacity(s.len());
+    for c in deunicode(s).to_lowercase().chars() {
+        if c.is_alphanumeric() {
+            anchor.push(c);
+        } else if !anchor.is_empty() && !anchor.ends_with('-') {
+            anchor.push('-');
+        }
+    }
+    if anchor.ends_with('-') {
+        anchor.pop();
+    }
+    anchor
+}
+
+/// Splits a trailing `{#custom-id}` from a heading's text.
+fn split_custom_id(text: &str) -> Option<(&str, &str)> {
+    let rest = text.trim_end().strip_suffix('}')?;
+    let start = rest.rfind("{#")?;
+    let id = &rest[start + 2..];
+    if id.is_empty() || id.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((rest[..start].trim_end(), id))
+}
+
+/// Splits a leading `{:lang}` language hint for inline code from the text following it.
+fn split_lang_hint(text: &str) -> Option<(&str, &str)> {
+    let rest = text.strip_prefix("{:")?;
+    let end = rest.find('}')?;
+    let lang = &rest[..end];
+    if lang.is_empty() || lang.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((lang, &rest[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Options, Parser};
+
+    fn render(markdown: &str) -> String {
+        let mut html = String::new();
+        html::push_html(
+            &mut html,
+            Transformer::new(Parser::new_ext(markdown, Options::all())).figures(true),
+        );
+        html
+    }
+
+    fn heading_ids(markdown: &str) -> Vec<String> {
+        let mut transformer = Transformer::new(Parser::new_ext(markdown, Options::all()));
+        transformer.by_ref().for_each(drop);
+        transformer
+            .headings()
+            .iter()
+            .map(|h| h.id.clone())
+            .collect()
+    }
+
+    fn callout(kind: Callout, title: &str) -> String {
+        format!(
+            r#"<aside class="callout callout-{}"><p class="callout-title"><span class="callout-icon" aria-hidden="true">{}</span>{}</p>"#,
+            kind.name(),
+            kind.icon(),
+            title
+        )
+    }
+
+    #[test]
+    fn toc_markers() {
+        for markdown in [
+            "[[toc]]\n",
+            "<!-- toc -->\n",
+            "Text\n<!-- toc -->\n",
+            "  <!-- toc -->",
+        ] {
+            assert!(render(markdown).contains(TOC_PLACEHOLDER), "{:?}", markdown);
+        }
+    }
+
+    #[test]
+    fn inline_toc_marker_is_kept() {
+        assert_eq!(render("Text <!-- toc -->\n"), "<p>Text <!-- toc --></p>\n");
+        assert_eq!(render("Text [[toc]]\n"), "<p>Text [[toc]]</p>\n");
+    }
+
+    #[test]
+    fn callout_marker() {
+        assert!(matches!(
+            split_callout_marker("[!note] Title"),
+            Some((Callout::Note, " Title"))
+        ));
+        assert!(matches!(
+            split_callout_marker("[!CAUTION]"),
+            Some((Callout::Caution, ""))
+        ));
+        assert!(split_callout_marker("[!UNKNOWN]").is_none());
+        assert!(split_callout_marker("text [!NOTE]").is_none());
+    }
+
+    #[test]
+    fn callout_with_default_title() {
+        // the parser splits the marker into several text events
+        assert_eq!(
+            render("> [!NOTE]\n> Some text.\n"),
+            format!(
+                "{}\n<p>Some text.</p>\n</aside>\n",
+                callout(Callout::Note, "Note")
+            )
+        );
+    }
+
+    #[test]
+    fn callout_with_custom_title() {
+        assert_eq!(
+            render("> [!WARNING] Be **careful**\n> Some text.\n"),
+            format!(
+                "{}\n<p>Some text.</p>\n</aside>\n",
+                callout(Callout::Warning, "Be <strong>careful</strong>")
+            )
+        );
+    }
+
+    #[test]
+    fn callout_with_only_a_marker() {
+        assert_eq!(
+            render("> [!TIP]\n"),
+            format!("{}\n</aside>\n", callout(Callout::Tip, "Tip"))
+        );
+    }
+
+    #[test]
+    fn blockquote_without_callout_marker() {
+        assert_eq!(
+            render("> [!UNKNOWN]\n> text\n"),
+            "<blockquote>\n<p>[!UNKNOWN]\ntext</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn figure() {
+        assert_eq!(
+            render("![alt](a.png \"A title\")\n"),
+            "<figure class=\"image\"><a href=\"a.png\"><img src=\"a.png\" alt=\"alt\" /></a>\
+             <figcaption>A title</figcaption></figure>\n"
+        );
+    }
+
+    #[test]
+    fn no_figure() {
+        for markdown in [
+            "![alt](a.png \"A title\"){nofigure}\n",
+            "![alt](a.png \"A title\") {nofigure}\n",
+        ] {
+            assert_eq!(
+                render(markdown),
+                "<p><img src=\"a.png\" alt=\"alt\" title=\"A title\" /></p>\n"
+            );
+        }
+    }
+
+    #[test]
+    fn no_figure_for_images_without_title_or_with_text() {
+        assert_eq!(
+            render("![alt](a.png)\n"),
+            "<p><img src=\"a.png\" alt=\"alt\" /></p>\n"
+        );
+        assert_eq!(
+            render("Text ![alt](a.png \"A title\")\n"),
+            "<p>Text <img src=\"a.png\" alt=\"alt\" title=\"A title\" /></p>\n"
+        );
+    }
+
+    #[test]
+    fn figures_disabled() {
+        let markdown = "![alt](a.png \"A title\")\n";
+        let mut html = String::new();
+        html::push_html(&mut html, Transformer::new(Parser::new(markdown)));
+        assert_eq!(
+            html,
+            "<p><img src=\"a.png\" alt=\"alt\" title=\"A title\" /></p>\n"
+        );
+    }
+
+    #[test]
+    fn custom_id() {
+        assert_eq!(split_custom_id("Title {#intro}"), Some(("Title", "intro")));
+        assert_eq!(split_custom_id("Title {#}"), None);
+        assert_eq!(split_custom_id("Title {#a b}"), None);
+        assert_eq!(split_custom_id("Title"), None);
+    }
+
+    #[test]
+    fn duplicate_heading_ids() {
+        assert_eq!(
+            heading_ids("# Title\n\n## A {#intro}\n\n## B {#intro}\n\n## Intro\n\n## Title\n"),
+            ["title", "intro", "intro-1", "intro-2", "title-1"]
+        );
+    }
 }
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::{check_links, create_listings, page_file_names, render_post, Error, Post};
+use crate::config::CONFIG;
+use crate::discover;
+
+/// How often the modification times of the watched files are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls the modification times of all posts and the files they depend on, and re-renders the
+/// affected posts (plus index, taxonomy pages and feed) whenever one of them changes. Errors are
+/// printed instead of ending the watch. `on_rebuild` is called after each rebuild.
+pub fn run(layout: &str, mut posts: Vec<Post>, on_rebuild: impl Fn()) -> Result<(), Error> {
+    let mut mtimes = snapshot(&posts);
+    eprintln!("watching {} posts for changes ...", posts.len());
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let changed = posts
+            .iter()
+            .enumerate()
+            .filter(|(_, post)| {
+                watched_files(post).any(|path| mtimes.get(path) != Some(&mtime(path)))
+            })
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        // pick up markdown files that got added to content directories (discovery errors are
+        // ignored, as they are caused by removed files, which are handled below)
+        let paths = discover::markdown_files().unwrap_or_default();
+        let added = paths
+            .iter()
+            .filter(|path| {
+                !posts.iter().any(|post| &post.source == *path)
+                    && mtimes.get(*path) != Some(&mtime(path))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if changed.is_empty() && added.is_empty() {
+            continue;
+        }
+        let pages = page_file_names(&paths);
+
+        // iterate in reverse so that removing a post does not shift the remaining indices
+        for i in changed.into_iter().rev() {
+            let source = posts[i].source.clone();
+            if !source.is_file() {
+                eprintln!("removed {}", source.display());
+                let post = posts.remove(i);
+                remove_page(&post)?;
+                continue;
+            }
+
+            eprintln!("rebuilding {}", source.display());
+            match render_post(layout, &source, &pages) {
+                Ok(post) => {
+                    // the page moved (e.g. due to a changed slug) or got unpublished, so remove
+                    // the old one
+                    if post.file_name != posts[i].file_name || !post.is_published() {
+                        remove_page(&posts[i])?;
+                    }
+                    posts[i] = post;
+                }
+                Err(err) => eprintln!("error: {}", err),
+            }
+        }
+
+        // remember files that failed to build to only retry them once they changed again
+        let mut failed = Vec::new();
+        for path in added {
+            eprintln!("building {}", path.display());
+            match render_post(layout, &path, &pages) {
+                Ok(post) => posts.push(post),
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    failed.push(path);
+                }
+            }
+        }
+
+        if let Err(err) = check_links(&posts) {
+            eprintln!("error: {}", err);
+        }
+        if let Err(err) = create_listings(layout, &mut posts) {
+            eprintln!("error: {}", err);
+        }
+        on_rebuild();
+
+        mtimes = snapshot(&posts);
+        for path in failed {
+            let mtime = mtime(&path);
+            mtimes.insert(path, mtime);
+        }
+    }
+}
+
+fn watched_files(post: &Post) -> impl Iterator<Item = &Path> {
+    std::iter::once(post.source.as_path()).chain(post.dependencies.iter().map(PathBuf::as_path))
+}
+
+fn snapshot(posts: &[Post]) -> HashMap<PathBuf, Option<SystemTime>> {
+    posts
+        .iter()
+        .flat_map(watched_files)
+        .map(|path| (path.to_path_buf(), mtime(path)))
+        .collect()
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn remove_page(post: &Post) -> Result<(), Error> {
+    let path = Path::new(CONFIG.out_dir()).join(&post.file_name);
+    if path.is_file() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}